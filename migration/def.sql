@@ -1,7 +1,10 @@
 CREATE TABLE commands (
     id VARBINARY(16) PRIMARY KEY NOT NULL,
     command_type VARCHAR(20) NOT NULL,
-    discussion_link VARCHAR(120) NOT NULL
+    discussion_link VARCHAR(120) NOT NULL,
+    heading VARCHAR(255) NOT NULL,
+    requester VARCHAR(255) NOT NULL,
+    finished BOOLEAN NOT NULL DEFAULT FALSE
 );
 
 CREATE TABLE command_target_namespaces (
@@ -28,11 +31,58 @@ CREATE TABLE command_to_categories (
     PRIMARY KEY (command_id, category)
 );
 
+CREATE TABLE command_excluded_pages (
+    command_id VARBINARY(16) NOT NULL,
+    title VARCHAR(255) NOT NULL,
+    CONSTRAINT command_excluded_pages_command
+        FOREIGN KEY (command_id) REFERENCES commands (id),
+    PRIMARY KEY (command_id, title)
+);
+
 CREATE TABLE operations (
     id VARBINARY(16) PRIMARY KEY NOT NULL,
     command_id VARBINARY(16) NOT NULL,
     page_id INTEGER NOT NULL,
     rev_id INTEGER NOT NULL,
+    changes JSON NOT NULL,
     CONSTRAINT operation_command
         FOREIGN KEY (command_id) REFERENCES commands (id)
 );
+
+CREATE TABLE move_operations (
+    id VARBINARY(16) PRIMARY KEY NOT NULL,
+    command_id VARBINARY(16) NOT NULL,
+    page_id INTEGER NOT NULL,
+    from_title VARCHAR(255) NOT NULL,
+    to_title VARCHAR(255) NOT NULL,
+    CONSTRAINT move_operation_command
+        FOREIGN KEY (command_id) REFERENCES commands (id)
+);
+
+CREATE TABLE command_progress (
+    command_id VARBINARY(16) NOT NULL,
+    title VARCHAR(255) NOT NULL,
+    status VARCHAR(20) NOT NULL,
+    message VARCHAR(255),
+    processed_at TIMESTAMP(6) NOT NULL DEFAULT CURRENT_TIMESTAMP(6),
+    CONSTRAINT command_progress_command
+        FOREIGN KEY (command_id) REFERENCES commands (id),
+    PRIMARY KEY (command_id, title)
+);
+
+CREATE TABLE handled_sections (
+    heading VARCHAR(255) NOT NULL,
+    requester VARCHAR(255) NOT NULL,
+    outcome VARCHAR(20) NOT NULL,
+    handled_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (heading, requester)
+);
+
+CREATE TABLE deferred_pages (
+    command_id VARBINARY(16) NOT NULL,
+    title VARCHAR(255) NOT NULL,
+    deferred_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT deferred_page_command
+        FOREIGN KEY (command_id) REFERENCES commands (id),
+    PRIMARY KEY (command_id, title)
+);
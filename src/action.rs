@@ -1,6 +1,7 @@
 use anyhow::Context;
 use mwapi_responses::query;
 use mwbot::Bot;
+use serde_json::Value;
 
 #[query(prop = "info", inprop = "protection")]
 pub struct InfoResponse {}
@@ -16,3 +17,215 @@ pub async fn get_page_info(
         .pop()
         .context("API response returned 0 pages")
 }
+
+/// Botアカウントが持つ権限を返す
+pub async fn get_user_rights(bot: &Bot) -> anyhow::Result<Vec<String>> {
+    let params = vec![
+        ("action", "query".to_string()),
+        ("meta", "userinfo".to_string()),
+        ("uiprop", "rights".to_string()),
+    ];
+
+    let resp = bot.api().get_value(params).await?;
+    let rights = resp["query"]["userinfo"]["rights"]
+        .as_array()
+        .context("API response does not contain rights")?
+        .iter()
+        .filter_map(|right| right.as_str())
+        .map(ToString::to_string)
+        .collect();
+    Ok(rights)
+}
+
+/// `title` のページを編集した利用者を、版ごとに新しい順で返す
+pub async fn get_revision_users(
+    bot: &Bot,
+    title: impl Into<String>,
+) -> anyhow::Result<Vec<String>> {
+    let params = vec![
+        ("action", "query".to_string()),
+        ("prop", "revisions".to_string()),
+        ("titles", title.into()),
+        ("rvprop", "user".to_string()),
+        ("rvlimit", "max".to_string()),
+    ];
+
+    let resp = bot.api().get_value(params).await?;
+    let users = resp["query"]["pages"][0]["revisions"]
+        .as_array()
+        .context("API response does not contain revisions")?
+        .iter()
+        .filter_map(|revision| revision["user"].as_str())
+        .map(ToString::to_string)
+        .collect();
+    Ok(users)
+}
+
+/// `from` のページを `to` へ移動する.
+/// `no_redirect` が `true` の場合、移動元にリダイレクトを残さない.
+pub async fn move_page(
+    bot: &Bot,
+    from: impl Into<String>,
+    to: impl Into<String>,
+    reason: impl Into<String>,
+    no_redirect: bool,
+) -> anyhow::Result<()> {
+    let mut params = vec![
+        ("action", "move".to_string()),
+        ("from", from.into()),
+        ("to", to.into()),
+        ("reason", reason.into()),
+        ("movetalk", "1".to_string()),
+    ];
+    if no_redirect {
+        params.push(("noredirect", "1".to_string()));
+    }
+
+    let _: Value = bot.api().post_with_token("csrf", params).await?;
+    Ok(())
+}
+
+/// `title` のページを削除する.
+pub async fn delete_page(
+    bot: &Bot,
+    title: impl Into<String>,
+    reason: impl Into<String>,
+) -> anyhow::Result<()> {
+    let params = vec![
+        ("action", "delete".to_string()),
+        ("title", title.into()),
+        ("reason", reason.into()),
+    ];
+
+    let _: Value = bot.api().post_with_token("csrf", params).await?;
+    Ok(())
+}
+
+/// `title` のページが `category` に所属しているか
+pub async fn is_category_member(
+    bot: &Bot,
+    title: impl Into<String>,
+    category: impl Into<String>,
+) -> anyhow::Result<bool> {
+    let params = vec![
+        ("action", "query".to_string()),
+        ("prop", "categories".to_string()),
+        ("titles", title.into()),
+        ("clcategories", category.into()),
+    ];
+
+    let resp = bot.api().get_value(params).await?;
+    Ok(resp["query"]["pages"][0]["categories"]
+        .as_array()
+        .is_some_and(|categories| !categories.is_empty()))
+}
+
+/// `title` のページが `category` に所属しているかと、参照読み込みしているページ.
+/// 参照読み込みしているページには、他のテンプレートを通して読み込んでいるものも含む
+pub async fn get_membership_and_templates(
+    bot: &Bot,
+    title: impl Into<String>,
+    category: impl Into<String>,
+) -> anyhow::Result<(bool, Vec<String>)> {
+    let params = vec![
+        ("action", "query".to_string()),
+        ("prop", "categories|templates".to_string()),
+        ("titles", title.into()),
+        ("clcategories", category.into()),
+        ("tllimit", "max".to_string()),
+    ];
+
+    let resp = bot.api().get_value(params).await?;
+    let page = &resp["query"]["pages"][0];
+    let is_member = page["categories"]
+        .as_array()
+        .is_some_and(|categories| !categories.is_empty());
+    let templates = page["templates"]
+        .as_array()
+        .map(|templates| {
+            templates
+                .iter()
+                .filter_map(|template| template["title"].as_str())
+                .map(ToString::to_string)
+                .collect()
+        })
+        .unwrap_or_default();
+    Ok((is_member, templates))
+}
+
+/// `titles` のうち存在しないページ. 1回の問い合わせで取得できる数ずつまとめて確かめる
+pub async fn get_missing_pages(bot: &Bot, titles: &[String]) -> anyhow::Result<Vec<String>> {
+    let mut missing = Vec::new();
+    for titles in titles.chunks(50) {
+        let params = vec![
+            ("action", "query".to_string()),
+            ("titles", titles.join("|")),
+        ];
+
+        let resp = bot.api().get_value(params).await?;
+        let pages = resp["query"]["pages"]
+            .as_array()
+            .context("API response does not contain pages")?;
+        missing.extend(
+            pages
+                .iter()
+                .filter(|page| page["missing"].as_bool().unwrap_or_default())
+                .filter_map(|page| page["title"].as_str())
+                .map(ToString::to_string),
+        );
+    }
+    Ok(missing)
+}
+
+/// `title` のページをパージし、所属カテゴリなどのリンク情報を更新する
+pub async fn purge_page(bot: &Bot, title: impl Into<String>) -> anyhow::Result<()> {
+    let params = vec![
+        ("action", "purge".to_string()),
+        ("titles", title.into()),
+        ("forcelinkupdate", "1".to_string()),
+    ];
+
+    let _ = bot.api().post_value(params).await?;
+    Ok(())
+}
+
+/// `category` に所属するページの数
+pub async fn get_category_size(bot: &Bot, category: impl Into<String>) -> anyhow::Result<u64> {
+    let params = vec![
+        ("action", "query".to_string()),
+        ("prop", "categoryinfo".to_string()),
+        ("titles", category.into()),
+    ];
+
+    let resp = bot.api().get_value(params).await?;
+    Ok(resp["query"]["pages"][0]["categoryinfo"]["size"]
+        .as_u64()
+        .unwrap_or_default())
+}
+
+/// `title` のページ上で `wikitext` を展開した場合に付与されるカテゴリを返す.
+/// カテゴリ名は `Category:` を含まず、空白は `_` になっている
+pub async fn expand_categories(
+    bot: &Bot,
+    title: impl Into<String>,
+    wikitext: impl Into<String>,
+) -> anyhow::Result<Vec<String>> {
+    let params = vec![
+        ("action", "expandtemplates".to_string()),
+        ("title", title.into()),
+        ("text", wikitext.into()),
+        ("prop", "categories".to_string()),
+    ];
+
+    let resp = bot.api().post_value(params).await?;
+    Ok(resp["expandtemplates"]["categories"]
+        .as_array()
+        .map(|categories| {
+            categories
+                .iter()
+                .filter_map(|category| category["category"].as_str())
+                .map(ToString::to_string)
+                .collect()
+        })
+        .unwrap_or_default())
+}
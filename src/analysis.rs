@@ -0,0 +1,59 @@
+use mwbot::parsoid::prelude::*;
+use mwbot::Bot;
+
+use crate::action::{expand_categories, get_membership_and_templates};
+use crate::title::CategoryNormalizer;
+
+/// `title` のページを `category` に所属させているテンプレートを返す.
+/// カテゴリタグを置換できなかったページについて、その理由を調べるために使う
+pub async fn find_category_templates(
+    bot: &Bot,
+    title: &str,
+    html: &ImmutableWikicode,
+    category: &str,
+) -> anyhow::Result<Vec<String>> {
+    let (is_member, used_templates) = get_membership_and_templates(bot, title, category).await?;
+    if !is_member {
+        return Ok(Vec::new());
+    }
+
+    // 本文で直接呼び出しているテンプレートのうち、参照読み込みの記録があるもの
+    let mut transclusions = Vec::new();
+    for template in html.clone().into_mutable().filter_templates()? {
+        let name = template.name();
+        if used_templates.contains(&name) && !transclusions.iter().any(|(known, _)| known == &name)
+        {
+            transclusions.push((name, transclusion_wikitext(&template)));
+        }
+    }
+
+    // カテゴリタグがないまま所属しているため、呼び出しているテンプレートが1つならそれが付与している.
+    // 複数の場合のみ、呼び出しごとに展開して付与されるカテゴリを確かめる
+    if transclusions.len() <= 1 {
+        return Ok(transclusions.into_iter().map(|(name, _)| name).collect());
+    }
+
+    let normalizer = CategoryNormalizer::new(bot);
+    let mut templates = Vec::new();
+    for (name, wikitext) in transclusions {
+        let categories = expand_categories(bot, title, wikitext).await?;
+        if categories
+            .iter()
+            .any(|expanded| normalizer.is_same(expanded, category))
+        {
+            templates.push(name);
+        }
+    }
+
+    Ok(templates)
+}
+
+/// テンプレートの呼び出しをウィキテキストに戻す
+fn transclusion_wikitext(template: &Template) -> String {
+    let mut wikitext = format!("{{{{{}", template.name_in_wikitext());
+    for (name, value) in template.params() {
+        wikitext.push_str(&format!("|{name}={value}"));
+    }
+    wikitext.push_str("}}");
+    wikitext
+}
@@ -3,15 +3,13 @@ use futures_util::{stream, StreamExt, TryStreamExt};
 use indexmap::IndexMap;
 use mwbot::parsoid::prelude::*;
 use mwbot::{Bot, SaveOptions};
-use queuebot::config::{self, DiscussionSummaryIconBindings, OnWikiConfig};
+use queuebot::config::{self, load_on_wiki_config, DiscussionSummaryIconBindings};
 use queuebot::util::{IntoWikicode, ListExt};
 use tap::{Pipe, Tap};
 use tracing::info;
 
 const DISCUSSION_CLOSE_TEMPLATES: &[&str] =
     &["Template:古い話題のはじめ", "Template:古い話題のおわり"];
-const CONFIG_JSON_URL: &str =
-    "https://ja.wikipedia.org/wiki/利用者:QueueBot/config.json?action=raw";
 const OUTPUT_PAGE: &str = "プロジェクト:カテゴリ関連/議論/アクティブな議論一覧";
 
 #[tokio::main]
@@ -19,10 +17,7 @@ async fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt().init();
     let bot = Bot::from_default_config().await?;
 
-    let on_wiki_config = reqwest::get(CONFIG_JSON_URL)
-        .await?
-        .json::<OnWikiConfig>()
-        .await?;
+    let on_wiki_config = load_on_wiki_config().await?;
 
     let discussion_summary = stream::iter(Utc::now().date_naive().iter_days().rev().take(30))
         .then(|date| {
@@ -1,20 +1,59 @@
+use std::env;
+use std::time::Duration;
+
+use indexmap::IndexMap;
 use mwbot::parsoid::prelude::*;
 use mwbot::Bot;
-use queuebot::command::parse::Parser;
-use queuebot::command::{CommandStatus, OperationStatus};
-use queuebot::config::load_config;
-use queuebot::{db, send_command_message, QUEUE_PAGE};
-use tracing::warn;
+use queuebot::action::get_page_info;
+use queuebot::command::fingerprint::{is_rerun_requested, SectionFingerprint};
+use queuebot::command::parse::{is_bot_prefix, Parser, TRIAL_PREFIX};
+use queuebot::command::{CommandStatus, OperationResult, OperationStatus};
+use queuebot::config::{load_config, load_on_wiki_config, QueueBotConfig};
+use queuebot::util::ShutdownSignal;
+use queuebot::{db, send_command_message, send_edit_request, send_trial_preview, QUEUE_PAGE};
+use tokio::time::{self, Instant};
+use tracing::{info, warn};
 
 macro_rules! send_command_message {
     ($id:expr, $queue_page:expr, $queue:expr, $result:expr, $message:expr, $statuses:expr) => {
+        send_command_message!($id, $queue_page, $queue, $result, $message, None, $statuses)
+    };
+    ($id:expr, $queue_page:expr, $queue:expr, $result:expr, $message:expr, $link:expr, $statuses:expr) => {
+        send_command_message!(
+            $id,
+            $queue_page,
+            $queue,
+            $result,
+            $message,
+            $link,
+            $statuses,
+            None
+        )
+    };
+    ($id:expr, $queue_page:expr, $queue:expr, $result:expr, $message:expr, $link:expr, $statuses:expr, $tree:expr) => {
+        send_command_message!(
+            $id,
+            $queue_page,
+            $queue,
+            $result,
+            $message,
+            $link,
+            $statuses,
+            $tree,
+            None
+        )
+    };
+    ($id:expr, $queue_page:expr, $queue:expr, $result:expr, $message:expr, $link:expr, $statuses:expr, $tree:expr, $disappeared:expr) => {
         match send_command_message(
             $id,
             $queue_page.clone(),
             $queue,
             $result,
             $message,
+            $link,
             $statuses,
+            $tree,
+            $disappeared,
         )
         .await
         {
@@ -38,6 +77,70 @@ async fn main() -> anyhow::Result<()> {
 
     db::init(&config.mysql).await?;
 
+    let Some(mut lock) = db::try_lock_instance().await? else {
+        warn!("Another queue_consume process is running. Exiting...");
+        return Ok(());
+    };
+
+    let shutdown = ShutdownSignal::listen()?;
+
+    // `--daemon` が指定された場合は終了せずにキューを監視し続ける
+    if !env::args().any(|arg| arg == "--daemon") {
+        consume_queue(&bot, &config, &shutdown).await?;
+        return lock.release().await;
+    }
+
+    let revision_check_interval =
+        Duration::from_secs(config.daemon.revision_check_interval_secs.max(1));
+    let poll_interval = Duration::from_secs(config.daemon.poll_interval_secs);
+    let mut last_revid = None;
+    let mut last_polled: Option<Instant> = None;
+
+    while !shutdown.is_received() {
+        let revid = queue_revision(&bot).await;
+        let is_updated = revid.is_some() && revid != last_revid;
+        let is_due = last_polled.map_or(true, |polled| polled.elapsed() >= poll_interval);
+
+        if is_updated || is_due {
+            if !lock.is_held().await {
+                anyhow::bail!("lost the instance lock");
+            }
+            if let Err(err) = consume_queue(&bot, &config, &shutdown).await {
+                warn!(?err, "could not consume queue");
+            }
+            // 結果の投稿による更新では再読込しない
+            last_revid = queue_revision(&bot).await.or(revid);
+            last_polled = Some(Instant::now());
+        }
+
+        tokio::select! {
+            _ = time::sleep(revision_check_interval) => {}
+            _ = shutdown.wait() => {}
+        }
+    }
+
+    info!("Shutting down");
+    lock.release().await
+}
+
+/// キューのページの最新版のID
+async fn queue_revision(bot: &Bot) -> Option<u64> {
+    match get_page_info(bot, QUEUE_PAGE).await {
+        Ok(info) => info.lastrevid,
+        Err(err) => {
+            warn!(page = QUEUE_PAGE, ?err, "could not get page info");
+            None
+        }
+    }
+}
+
+/// キューを読み込み、未実行のコマンドを順に実行する.
+/// 停止要求を受け取った場合は実行中のコマンドを中断して終了する
+async fn consume_queue(
+    bot: &Bot,
+    config: &QueueBotConfig,
+    shutdown: &ShutdownSignal,
+) -> anyhow::Result<()> {
     let mut queue_page = bot.page(QUEUE_PAGE)?;
     let queue_html = queue_page.html().await?.into_mutable();
 
@@ -49,53 +152,146 @@ async fn main() -> anyhow::Result<()> {
         .filter(|section| !is_done(section))
         .collect::<Vec<_>>();
 
+    // Wiki上の設定は更新されうるため、キューを読み込むたびに取得する
+    let template_rules = match load_on_wiki_config().await {
+        Ok(config) => config.template_parameter_rules,
+        Err(err) => {
+            warn!(?err, "could not load on-wiki config");
+            Vec::new()
+        }
+    };
+
     for queue in queues {
-        let parser = match Parser::new(bot.clone(), &queue, false) {
-            Ok(command) => command,
-            Err(err) => {
-                warn!(?err, "parsing error occurred");
-                send_command_message!(None, queue_page, &queue, "不受理", &err.to_string(), None);
-                continue;
-            }
-        };
-        let Some(command) = parser.parse() else {
-            let section_name = queue
-                .heading()
-                .unwrap() // SAFETY: pseudo checked
-                .text_contents();
-            warn!(section_name = ?section_name, "Invalid command format");
-            send_command_message!(
-                None,
-                queue_page,
-                &queue,
-                "不受理",
-                "不明なコマンドです",
-                None
-            );
-            continue;
+        if shutdown.is_received() {
+            break;
+        }
+
+        let fingerprint = SectionFingerprint::new(&queue);
+        let mut summary = String::new();
+        let status = if is_handled(&fingerprint, &queue).await {
+            CommandStatus::Skipped
+        } else {
+            let parser = match Parser::new(bot.clone(), &queue, false) {
+                Ok(parser) => parser
+                    .template_rules(template_rules.clone())
+                    .replacers(config.command.replacers.clone()),
+                Err(err) => {
+                    warn!(?err, "parsing error occurred");
+                    send_command_message!(
+                        None,
+                        queue_page,
+                        &queue,
+                        "不受理",
+                        &err.to_string(),
+                        None
+                    );
+                    store_outcome(&fingerprint, "不受理").await;
+                    continue;
+                }
+            };
+            let command = match parser.parse() {
+                Ok(command) => command,
+                Err(err) => {
+                    let section_name = queue
+                        .heading()
+                        .unwrap() // SAFETY: pseudo checked
+                        .text_contents();
+                    warn!(section_name = ?section_name, ?err, "Invalid command format");
+                    send_command_message!(
+                        None,
+                        queue_page,
+                        &queue,
+                        "不受理",
+                        &err.to_string(),
+                        None
+                    );
+                    store_outcome(&fingerprint, "不受理").await;
+                    continue;
+                }
+            };
+
+            summary = command.summary().to_string();
+            command.execute(&config.command, shutdown).await
         };
+        let outcome = outcome_of(&status);
 
-        match command.execute().await {
-            CommandStatus::Done { id, statuses } => {
+        match status {
+            CommandStatus::Done {
+                id,
+                mut statuses,
+                remaining,
+                tree,
+                dump_diff,
+            } => {
+                let mut message = done_message(&statuses);
+                if !remaining.is_empty() {
+                    message.push_str(&format!(" ({}件がカテゴリに残っています)", remaining.len()));
+                }
+                if let Some(tree) = &tree {
+                    message.push_str(&format!(
+                        " (下位カテゴリを{}件たどりました)",
+                        tree.descendants().len()
+                    ));
+                }
+                // 保護されたページは管理者に編集を依頼する
+                let edit_request = send_edit_request(bot, &id, &summary, &statuses)
+                    .await
+                    .unwrap_or_else(|err| {
+                        warn!(?err, "could not save edit request");
+                        None
+                    });
+                if edit_request.is_some() {
+                    message.push_str(" 保護されたページの編集依頼:");
+                }
+                let dump_diff = dump_diff.filter(|diff| !diff.is_empty());
+                if let Some(dump_diff) = &dump_diff {
+                    message.push_str(&format!(
+                        " (ダンプの時点から{}件が新たに所属し、{}件が外れていました)",
+                        dump_diff.appeared.len(),
+                        dump_diff.disappeared.len()
+                    ));
+                }
+                // 残っているページは対応が必要なため、結果の一覧に理由を記載する
+                for (title, reason) in remaining {
+                    statuses.insert(
+                        title,
+                        Err(format!("カテゴリに残っています: {}", reason.message())),
+                    );
+                }
                 send_command_message!(
                     Some(&id),
                     queue_page,
                     &queue,
                     "完了",
-                    &format!(
-                        "{}件の操作を完了しました",
-                        statuses
-                            .iter()
-                            .filter(|(_page, result)| **result != Ok(OperationStatus::Skipped))
-                            .count()
-                    ),
-                    Some(statuses)
+                    &message,
+                    edit_request.as_deref(),
+                    Some(statuses),
+                    tree.as_ref(),
+                    // 新たに所属したページは結果の一覧にあるため、外れたページのみ記載する
+                    dump_diff.as_ref().map(|diff| diff.disappeared.as_slice())
                 );
             }
-            CommandStatus::EmergencyStopped => {
-                send_command_message!(None, queue_page, &queue, "保留", "緊急停止しました", None);
+            CommandStatus::EmergencyStopped { id } => {
+                send_command_message!(
+                    Some(&id),
+                    queue_page,
+                    &queue,
+                    "保留",
+                    "緊急停止しました。再実行時は続きから再開します",
+                    None
+                );
                 continue;
             }
+            CommandStatus::Interrupted { id } => {
+                send_command_message!(
+                    Some(&id),
+                    queue_page,
+                    &queue,
+                    "保留",
+                    "Botの停止のため中断しました。再実行時は続きから再開します",
+                    None
+                );
+            }
             CommandStatus::Error {
                 id,
                 statuses,
@@ -110,6 +306,25 @@ async fn main() -> anyhow::Result<()> {
                     Some(statuses)
                 );
             }
+            CommandStatus::Deferred {
+                id,
+                statuses,
+                is_newly_deferred,
+            } => {
+                // 使用中のページを再度処理するたびに投稿しないよう、新たに後回しにした場合のみ報告する
+                if is_newly_deferred {
+                    send_command_message!(
+                        Some(&id),
+                        queue_page,
+                        &queue,
+                        "保留",
+                        "使用中のページがあるため、使用中の表示が外れてから再度編集します",
+                        Some(statuses)
+                    );
+                } else {
+                    info!(%id, "Pages are still in use");
+                }
+            }
             CommandStatus::CategoryEmpty => {
                 send_command_message!(
                     None,
@@ -120,34 +335,128 @@ async fn main() -> anyhow::Result<()> {
                     None
                 );
             }
+            CommandStatus::Trial { id, statuses } => {
+                let preview = match send_trial_preview(bot, &id, &statuses).await {
+                    Ok(preview) => preview,
+                    Err(err) => {
+                        warn!(?err, "could not save trial preview");
+                        send_command_message!(
+                            Some(&id),
+                            queue_page,
+                            &queue,
+                            "中止",
+                            "試行結果を投稿できませんでした",
+                            Some(statuses)
+                        );
+                        continue;
+                    }
+                };
+                send_command_message!(
+                    Some(&id),
+                    queue_page,
+                    &queue,
+                    "試行",
+                    "試行結果を投稿しました:",
+                    Some(&preview),
+                    None
+                );
+            }
             CommandStatus::Skipped => {
-                // do nothing
+                info!(?fingerprint, "Skipping already handled queue");
             }
         }
+
+        if let Some(outcome) = outcome {
+            store_outcome(&fingerprint, outcome).await;
+        }
     }
 
     Ok(())
 }
 
-/// セクション名は `Bot:` で始まるか
+/// 同じキューを既に処理しているか. 再実行が依頼されている場合は処理していないとみなす
+async fn is_handled(fingerprint: &SectionFingerprint, section: &Section) -> bool {
+    if is_rerun_requested(section) {
+        return false;
+    }
+
+    match db::find_section_outcome(fingerprint).await {
+        Ok(outcome) => outcome.is_some(),
+        Err(err) => {
+            warn!(?fingerprint, ?err, "could not get queue outcome");
+            false
+        }
+    }
+}
+
+async fn store_outcome(fingerprint: &SectionFingerprint, outcome: &str) {
+    if let Err(err) = db::store_section_outcome(fingerprint, outcome).await {
+        warn!(?fingerprint, ?err, "could not store queue outcome");
+    }
+}
+
+/// 処理済みとして記録する結果. 中断した場合や使用中のページを残している場合は、再開できるように記録しない
+fn outcome_of(status: &CommandStatus) -> Option<&'static str> {
+    match status {
+        CommandStatus::Done { .. } => Some("完了"),
+        CommandStatus::Error { .. } => Some("中止"),
+        CommandStatus::CategoryEmpty => Some("不可能"),
+        CommandStatus::Trial { .. } => Some("試行"),
+        CommandStatus::EmergencyStopped { .. }
+        | CommandStatus::Interrupted { .. }
+        | CommandStatus::Deferred { .. }
+        | CommandStatus::Skipped => None,
+    }
+}
+
+/// `N件の操作を完了しました` の形式で完了報告のメッセージを作る
+fn done_message(statuses: &IndexMap<String, OperationResult>) -> String {
+    let count = |status: OperationStatus| {
+        statuses
+            .values()
+            .filter(|result| result.as_ref() == Ok(&status))
+            .count()
+    };
+
+    let mut message = format!("{}件の操作を完了しました", count(OperationStatus::Done));
+    let excluded = count(OperationStatus::Excluded);
+    if excluded > 0 {
+        message.push_str(&format!(" ({excluded}件を除外しました)"));
+    }
+    message
+}
+
+/// セクション名は `Bot:` または `Bot (試行):` で始まるか
 fn is_prefixed_as_bot(section: &Section) -> bool {
     let heading = section.heading().unwrap(); // SAFETY: not pseudo section
     let prefix_node = heading.descendants().nth(1).unwrap();
     if let Some(prefix) = prefix_node.as_text() {
-        prefix.borrow().starts_with("Bot:")
+        is_bot_prefix(&prefix.borrow())
     } else {
         false
     }
 }
 
-/// そのキューは既に実行済み({{BOTREQ|完了}}が貼られている)か
+/// そのキューは既に実行済み({{BOTREQ|完了}}が貼られている)か.
+/// {{BOTREQ|試行}}は、セクション名から試行のプレフィックスが外されるまでは実行済みとみなす
 fn is_done(section: &Section) -> bool {
+    let is_trial = section.heading().is_some_and(|heading| {
+        heading
+            .text_contents()
+            .trim_start()
+            .starts_with(TRIAL_PREFIX)
+    });
     let children = section.inclusive_descendants();
     let mut templates = children
         .flat_map(|child| child.filter_templates())
         .flatten();
 
     templates.any(|template| {
-        template.name() == "Template:BOTREQ" && template.param("1") == Some("完了".to_string())
+        template.name() == "Template:BOTREQ"
+            && match template.param("1").as_deref() {
+                Some("完了") => true,
+                Some("試行") => is_trial,
+                _ => false,
+            }
     })
 }
@@ -3,6 +3,8 @@ use std::env;
 use anyhow::Context as _;
 use futures_util::StreamExt as _;
 use mwbot::{Bot, SaveOptions};
+use queuebot::action::{delete_page, get_revision_users, get_user_rights, move_page};
+use queuebot::BOT_NAME;
 use sqlx::{FromRow, MySql, MySqlPool, QueryBuilder};
 use ulid::Ulid;
 use uuid::Uuid;
@@ -49,6 +51,84 @@ async fn main() -> anyhow::Result<()> {
             tracing::error!(title = page_title, err = ?err);
         }
     }
+
+    // 移動元には{{Category redirect}}が置かれているため、削除してから移動し直す
+    let mut query: QueryBuilder<'_, MySql> = QueryBuilder::new(
+        "SELECT page_id, from_title, to_title FROM move_operations WHERE command_id IN (",
+    );
+    let mut separated = query.separated(", ");
+    command_ids.iter().for_each(|id| {
+        separated.push_bind(id);
+    });
+    separated.push_unseparated(")");
+
+    let query = query.build_query_as::<MoveOperation>();
+    let mut move_operations = query.fetch(&pool);
+
+    // 移動元のリダイレクトを削除できない場合は、移動し直さずに手作業での対応を求める.
+    // {{Category redirect}}を設置した移動元は複数の版を持つため、delete-redirect では削除できない
+    let can_delete = get_user_rights(&bot)
+        .await
+        .inspect_err(|err| tracing::error!(message = "Botの権限を取得できませんでした", err = ?err))
+        .is_ok_and(|rights| rights.iter().any(|right| right == "delete"));
+    let mut manual = vec![];
+
+    while let Some(operation) = move_operations.next().await {
+        let operation = match operation {
+            Ok(operation) => operation,
+            Err(err) => {
+                tracing::error!(err = ?err);
+                continue;
+            }
+        };
+        let page = bot.page_from_id(operation.page_id as u64).await?;
+        let page_title = page.title().to_string();
+        if !can_delete {
+            manual.push((page_title, operation.from_title, "削除権限がありません"));
+            continue;
+        }
+        // Bot以外の編集がある場合、削除するとその版も失われる
+        match get_revision_users(&bot, &operation.from_title).await {
+            Ok(users) if users.iter().all(|user| user == BOT_NAME) => {}
+            Ok(_) => {
+                manual.push((page_title, operation.from_title, "Bot以外の編集があります"));
+                continue;
+            }
+            Err(err) => {
+                tracing::error!(title = operation.from_title, err = ?err);
+                manual.push((
+                    page_title,
+                    operation.from_title,
+                    "版の履歴を取得できませんでした",
+                ));
+                continue;
+            }
+        }
+        if let Err(err) = delete_page(&bot, &operation.from_title, "BOT: Undo operation").await {
+            tracing::error!(title = operation.from_title, err = ?err);
+            continue;
+        }
+        if let Err(err) = move_page(
+            &bot,
+            &page_title,
+            &operation.from_title,
+            "BOT: Undo operation",
+            true,
+        )
+        .await
+        {
+            tracing::error!(title = page_title, to = operation.to_title, err = ?err);
+        }
+    }
+
+    for (title, from_title, reason) in manual {
+        tracing::warn!(
+            message = "移動し直せなかったため、手作業で対応してください",
+            title,
+            to = from_title,
+            reason
+        );
+    }
     Ok(())
 }
 
@@ -57,3 +137,10 @@ struct Operation {
     page_id: i32,
     rev_id: i64,
 }
+
+#[derive(Debug, FromRow)]
+struct MoveOperation {
+    page_id: i32,
+    from_title: String,
+    to_title: String,
+}
@@ -1,32 +1,92 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
+use std::future;
+use std::pin::pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use derivative::Derivative;
+use futures_util::{stream, StreamExt as _};
 use indexmap::IndexMap;
 use mwbot::parsoid::prelude::*;
 use mwbot::{Bot, Page, SaveOptions};
-use tracing::{info, warn};
+use tracing::{info, info_span, warn, Instrument as _};
 use ulid::Ulid;
 
-use crate::db::{store_command, store_operation, CommandType};
-use crate::generator::list_category_members;
+use self::fingerprint::SectionFingerprint;
+use self::marker::{find_marker, PageMarker};
+use self::protection::can_edit;
+use self::verify::{find_remaining_members, RemainingReason};
+use crate::action::{
+    get_category_size,
+    get_missing_pages,
+    get_page_info,
+    get_user_rights,
+    move_page,
+};
+use crate::analysis::find_category_templates;
+use crate::config::{CommandConfig, DumpFiles};
+use crate::db::{
+    find_resumable_command,
+    finish_command,
+    is_category_moved,
+    load_deferred_pages,
+    load_progress,
+    store_command,
+    store_deferred_page,
+    store_move_operation,
+    store_operation,
+    store_progress,
+    CommandType,
+};
+use crate::generator::dump::{dump_source, DumpDiff};
+use crate::generator::{
+    list_category_members,
+    member_sources,
+    traverse_subcategories,
+    CategoryTree,
+    MemberSource,
+    RecordingSource,
+};
 use crate::is_emergency_stopped;
-use crate::replacer::CategoryReplacerList;
+use crate::replacer::{summary_with_changes, CategoryReplacerList, ChangeRecord};
+use crate::util::{line_diff, SaveLimiter, ShutdownSignal};
 
+pub mod fingerprint;
+mod marker;
 pub mod parse;
+mod protection;
+pub mod verify;
+
+/// 下位カテゴリの名前を置き換えた操作先と、同じ操作を適用する置換処理を作る.
+/// 操作を当てはめられない下位カテゴリの場合は `None` を返す
+pub type SubcategoryReplacers<R> = Arc<dyn Fn(&str) -> Option<(Vec<String>, R)> + Send + Sync>;
 
 #[derive(Derivative)]
 #[derivative(Debug)]
 pub struct Command<R> {
     bot: Bot,
     dry_run: bool,
+    /// 試行モードの場合、保存せずに差分を記録する
+    pub(crate) trial: bool,
     pub(crate) id: Ulid,
     pub(crate) from: String,
     pub(crate) to: Vec<String>,
     pub(crate) discussion_link: String,
     pub(crate) namespaces: Vec<u32>,
-    replacers: R,
+    /// 操作から除外するページ
+    pub(crate) excluded: Vec<String>,
+    /// たどる下位カテゴリの深さ. 0の場合はたどらない
+    pub(crate) subcategory_depth: usize,
+    /// たどった下位カテゴリの所属ページに使う置換処理
     #[derivative(Debug = "ignore")]
-    save_opts: SaveOptions,
+    pub(crate) subcategory_replacers: Option<SubcategoryReplacers<R>>,
+    /// 中断したコマンドを再開する際に同じコマンドを特定するための、セクション名と依頼者
+    pub(crate) fingerprint: SectionFingerprint,
+    replacers: R,
+    summary: String,
     pub(crate) command_type: CommandType,
 }
 
@@ -34,8 +94,111 @@ impl<R> Command<R>
 where
     R: CategoryReplacerList + Debug,
 {
-    pub async fn execute(self) -> CommandStatus {
-        if let Err(err) = store_command(&self).await {
+    /// コマンドを実行する.
+    /// 同じセクションのコマンドが中断されていた場合は、そのIDで続きから再開する
+    pub async fn execute(
+        mut self,
+        config: &CommandConfig,
+        shutdown: &ShutdownSignal,
+    ) -> CommandStatus {
+        let status = self.run(config, shutdown).await;
+        // 中断した場合は再開できるように未完了のままにする
+        // 使用中のページを残している場合も、後で再度処理できるように未完了のままにする
+        // 試行はデータベースに記録しないため、完了も記録しない
+        if !self.trial
+            && !matches!(
+                status,
+                CommandStatus::EmergencyStopped { .. }
+                    | CommandStatus::Interrupted { .. }
+                    | CommandStatus::Deferred { .. }
+            )
+        {
+            if let Err(err) = finish_command(&self.id).await {
+                warn!(message = "コマンドの完了を記録できませんでした", err = ?err);
+            }
+        }
+        status
+    }
+
+    async fn run(&mut self, config: &CommandConfig, shutdown: &ShutdownSignal) -> CommandStatus {
+        let resumable = if self.trial {
+            None
+        } else {
+            match find_resumable_command(&self.fingerprint).await {
+                Ok(resumable) => resumable,
+                Err(err) => {
+                    return CommandStatus::Error {
+                        id: self.id,
+                        statuses: IndexMap::new(),
+                        message: format!(
+                            "中断されたコマンドをデータベースから取得できませんでした: {:?}",
+                            err
+                        ),
+                    };
+                }
+            }
+        };
+
+        // 移動先が既に存在すると移動できないため、所属ページを編集する前に確かめる
+        let needs_move = self.command_type == CommandType::Move
+            && match resumable {
+                Some(id) => !is_category_moved(&id).await.unwrap_or_else(|err| {
+                    warn!(message = "カテゴリページの移動記録を取得できませんでした", err = ?err);
+                    false
+                }),
+                None => true,
+            };
+        if needs_move {
+            if let Err(message) = self.check_move_target().await {
+                return CommandStatus::Error {
+                    id: resumable.unwrap_or(self.id),
+                    statuses: IndexMap::new(),
+                    message,
+                };
+            }
+        }
+
+        // たどった下位カテゴリの所属ページも、下位カテゴリ用の置換処理で同じコマンドとして操作する
+        let mut tree = match self.traverse_subcategories(config).await {
+            Ok(tree) => tree,
+            Err(message) => {
+                return CommandStatus::Error {
+                    id: resumable.unwrap_or(self.id),
+                    statuses: IndexMap::new(),
+                    message,
+                };
+            }
+        };
+        let (subcategories, targets) = self.match_subcategories(tree.as_mut());
+        // 名前を置き換えた操作先がないと所属ページが存在しないカテゴリに移るため、編集する前に確かめる
+        if let Err(message) = self.check_subcategory_targets(&targets).await {
+            return CommandStatus::Error {
+                id: resumable.unwrap_or(self.id),
+                statuses: IndexMap::new(),
+                message,
+            };
+        }
+
+        let mut statuses = IndexMap::new();
+        if let Some(id) = resumable {
+            info!(%id, section = self.fingerprint.heading, "Resuming interrupted command");
+            self.resume_as(id);
+            match load_progress(&id).await {
+                Ok(progress) => statuses.extend(progress),
+                Err(err) => {
+                    return CommandStatus::Error {
+                        id,
+                        statuses,
+                        message: format!(
+                            "コマンドの進捗をデータベースから取得できませんでした: {:?}",
+                            err
+                        ),
+                    };
+                }
+            }
+        } else if self.trial {
+            // 試行はロールバックや再開の対象にならないよう、データベースに記録しない
+        } else if let Err(err) = store_command(self).await {
             return CommandStatus::Error {
                 id: Ulid::new(),
                 statuses: IndexMap::new(),
@@ -43,53 +206,632 @@ where
             };
         }
 
-        let mut category_members =
-            list_category_members(&self.bot, &self.from, self.namespaces.clone()).await;
+        if needs_move {
+            match self.move_category_page().await {
+                Ok(None) => {}
+                // 移動は記録済みのため処理を続け、移動元の状態として報告する
+                Ok(Some(message)) => {
+                    let result = Err(message);
+                    if !self.trial {
+                        if let Err(err) = store_progress(&self.id, &self.from, &result).await {
+                            warn!(message = "進捗をデータベースに保存できませんでした", title = self.from, err = ?err);
+                        }
+                    }
+                    statuses.insert(self.from.clone(), result);
+                }
+                Err(message) => {
+                    return CommandStatus::Error {
+                        id: self.id,
+                        statuses,
+                        message,
+                    };
+                }
+            }
+        }
 
-        let mut statuses = IndexMap::new();
-        while let Some(page) = category_members.recv().await {
-            if is_emergency_stopped(&self.bot).await {
-                return CommandStatus::EmergencyStopped;
+        // 中断前にエラーや編集競合となったページ、使用中だったページは再度処理する
+        let processed = statuses
+            .iter()
+            .filter(|(_, result)| {
+                !matches!(
+                    result,
+                    Err(_) | Ok(OperationStatus::EditConflicted | OperationStatus::Deferred)
+                )
+            })
+            .map(|(title, _)| title.clone())
+            .collect::<HashSet<_>>();
+        // 使用中だったページは再度処理した結果に置き換える. カテゴリから外れていた場合は報告しない
+        statuses.retain(|_, result| result != &Ok(OperationStatus::Deferred));
+
+        // 使用中のため後回しにしたページと、最初に後回しにしてからの経過時間
+        let deferred = match resumable {
+            Some(id) => load_deferred_pages(&id).await.unwrap_or_else(|err| {
+                warn!(message = "使用中のページの記録を取得できませんでした", err = ?err);
+                Vec::new()
+            }),
+            None => Vec::new(),
+        }
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+        let in_use_timeout = Duration::from_secs(config.in_use_timeout_secs);
+
+        // 保護されたページを編集できるかの判断に使う
+        let rights = get_user_rights(&self.bot)
+            .await
+            .inspect_err(|err| warn!(message = "Botの権限を取得できませんでした", err = ?err))
+            .ok();
+
+        // 所属ページのみを返す取得元の結果. ダンプの時点の所属ページと比べるのに使う
+        let listed = Arc::new(Mutex::new(HashSet::new()));
+        let root_sources = self
+            .member_sources(config)
+            .into_iter()
+            .map(|source| {
+                if source.lists_members_only() {
+                    Box::new(RecordingSource::new(source, listed.clone())) as _
+                } else {
+                    source
+                }
+            })
+            .collect();
+        let mut members = vec![(
+            list_category_members(&self.bot, &self.from, self.namespaces.clone(), root_sources)
+                .await,
+            self.from.as_str(),
+            &self.replacers,
+        )];
+        for (category, replacers) in &subcategories {
+            members.push((
+                list_category_members(
+                    &self.bot,
+                    category,
+                    self.namespaces.clone(),
+                    self.member_sources(config),
+                )
+                .await,
+                category.as_str(),
+                replacers,
+            ));
+        }
+        let save_limiter = SaveLimiter::new(Duration::from_secs(config.save_interval_secs));
+
+        // ページの取得と置換は並行して行い、結果は取得した順に並べる.
+        // 停止する場合は新しいページの処理を始めず、処理中のページの完了を待つ
+        let stopping = AtomicBool::new(false);
+        // 取得し直しても一覧を取得できなかった取得元の数
+        let list_errors = AtomicUsize::new(0);
+        let results = stream::iter(members)
+            .flat_map(|(rx, category, replacers)| {
+                stream::unfold(rx, |mut rx| async move {
+                    rx.recv().await.map(|page| (page, rx))
+                })
+                .map(move |page| (page, category, replacers))
+            })
+            .filter_map(|(page, category, replacers)| {
+                let list_errors = &list_errors;
+                async move {
+                    match page {
+                        Ok(page) => Some((page, category, replacers)),
+                        Err(err) => {
+                            warn!("Error while getting: {:?}", err);
+                            list_errors.fetch_add(1, Ordering::Relaxed);
+                            None
+                        }
+                    }
+                }
+            })
+            .filter(|(page, _, _)| future::ready(!processed.contains(page.title())))
+            .take_while(|_| future::ready(!stopping.load(Ordering::Relaxed)))
+            .map(|(page, category, replacers)| {
+                let is_in_use_expired = deferred
+                    .get(page.title())
+                    .is_some_and(|elapsed| *elapsed >= in_use_timeout);
+                self.process_member(
+                    page,
+                    category,
+                    replacers,
+                    rights.as_deref(),
+                    is_in_use_expired,
+                    &save_limiter,
+                    shutdown,
+                )
+            })
+            .buffered(config.concurrency.max(1));
+        let mut results = pin!(results);
+
+        while let Some(result) = results.next().await {
+            match result {
+                Some((title, result)) => {
+                    statuses.insert(title, result);
+                }
+                None => stopping.store(true, Ordering::Relaxed),
             }
+        }
+
+        if stopping.load(Ordering::Relaxed) {
+            return if shutdown.is_received() {
+                CommandStatus::Interrupted { id: self.id }
+            } else {
+                CommandStatus::EmergencyStopped { id: self.id }
+            };
+        }
 
-            let Ok(page) = page else {
-                warn!("Error while getting: {:?}", page);
-                continue;
+        // 一覧を取得しきれなかった場合は、処理されていないページがあるため完了としない
+        if list_errors.load(Ordering::Relaxed) > 0 {
+            let message = self.list_error_message(statuses.len()).await;
+            return CommandStatus::Error {
+                id: self.id,
+                statuses,
+                message,
             };
-            statuses.insert(page.title().to_string(), self.process_page(page).await);
         }
 
+        let deferred_titles = statuses
+            .iter()
+            .filter(|(_, result)| matches!(result, Ok(OperationStatus::Deferred)))
+            .map(|(title, _)| title)
+            .collect::<Vec<_>>();
+
         if statuses.is_empty() {
             CommandStatus::CategoryEmpty
+        } else if self.trial {
+            CommandStatus::Trial {
+                id: self.id,
+                statuses,
+            }
+        } else if !deferred_titles.is_empty() {
+            let is_newly_deferred = deferred_titles
+                .iter()
+                .any(|title| !deferred.contains_key(*title));
+            CommandStatus::Deferred {
+                id: self.id,
+                statuses,
+                is_newly_deferred,
+            }
         } else {
+            let remaining = self.verify_remaining(&statuses).await;
+            let dump_diff = match &config.dump {
+                Some(dump) if !dump.list_members => {
+                    let listed = std::mem::take(&mut *listed.lock().unwrap());
+                    self.compare_with_dump(&dump.files, &listed).await
+                }
+                _ => None,
+            };
             CommandStatus::Done {
                 id: self.id,
                 statuses,
+                remaining,
+                tree,
+                dump_diff,
             }
         }
     }
 
-    async fn process_page(&self, page: Page) -> OperationResult {
-        let html = page.html().await.map_err(|err| {
-            warn!(message = "ページの取得中にエラーが発生しました", err = ?err);
-            "ページの取得中にエラーが発生しました".to_string()
-        })?;
+    /// 所属ページの取得元. ダンプから取得する設定の場合はAPIを使わない
+    fn member_sources(&self, config: &CommandConfig) -> Vec<Box<dyn MemberSource>> {
+        match &config.dump {
+            Some(dump) if dump.list_members => vec![dump_source(&dump.files)],
+            _ => member_sources(&self.command_type),
+        }
+    }
+
+    /// APIから取得した所属ページ `listed` をダンプの時点の所属ページと比べる.
+    /// 検索で見つけたページは所属ページではないため `listed` に含めない
+    async fn compare_with_dump(
+        &self,
+        files: &DumpFiles,
+        listed: &HashSet<String>,
+    ) -> Option<DumpDiff> {
+        let mut members = list_category_members(
+            &self.bot,
+            &self.from,
+            self.namespaces.clone(),
+            vec![dump_source(files)],
+        )
+        .await;
+        let mut snapshot = HashSet::new();
+        while let Some(page) = members.recv().await {
+            match page {
+                Ok(page) => {
+                    snapshot.insert(page.title().to_string());
+                }
+                Err(err) => {
+                    warn!(message = "ダンプから所属ページを取得できませんでした", err = ?err);
+                    return None;
+                }
+            }
+        }
+        Some(DumpDiff::new(listed, &snapshot))
+    }
+
+    /// 一覧を取得しきれなかった場合のメッセージ. 処理されていない可能性のあるページ数はカテゴリのページ数から見積もる
+    async fn list_error_message(&self, processed: usize) -> String {
+        match get_category_size(&self.bot, &self.from).await {
+            Ok(size) => format!(
+                "ページの一覧を取得できなかったため、最大{}件のページが処理されていない可能性があります",
+                size.saturating_sub(processed as u64)
+            ),
+            Err(err) => {
+                warn!(message = "カテゴリのページ数を取得できませんでした", err = ?err);
+                "ページの一覧を取得できなかったため、処理されていないページがある可能性があります"
+                    .to_string()
+            }
+        }
+    }
+
+    /// 下位カテゴリをたどる. 深さが指定されていない場合は `None` を返す
+    async fn traverse_subcategories(
+        &self,
+        config: &CommandConfig,
+    ) -> Result<Option<CategoryTree>, String> {
+        let depth = self.subcategory_depth.min(config.max_subcategory_depth);
+        if depth == 0 {
+            return Ok(None);
+        }
+
+        traverse_subcategories(&self.bot, &self.from, depth)
+            .await
+            .map(Some)
+            .map_err(|err| {
+                warn!(message = "下位カテゴリの取得に失敗しました", err = ?err);
+                "下位カテゴリの取得に失敗しました".to_string()
+            })
+    }
+
+    /// たどった下位カテゴリのうち操作を当てはめられるものとその置換処理、名前を置き換えた操作先.
+    /// 当てはめられない下位カテゴリは、報告できるよう `tree` に記録する
+    fn match_subcategories(
+        &self,
+        tree: Option<&mut CategoryTree>,
+    ) -> (Vec<(String, R)>, Vec<String>) {
+        let (Some(tree), Some(build)) = (tree, &self.subcategory_replacers) else {
+            return (Vec::new(), Vec::new());
+        };
+
+        let mut subcategories = Vec::new();
+        let mut targets = Vec::new();
+        let mut skipped = Vec::new();
+        for category in tree.descendants() {
+            match build(&category) {
+                Some((renamed, replacers)) => {
+                    targets.extend(renamed.into_iter().filter(|target| target != &category));
+                    subcategories.push((category, replacers));
+                }
+                None => {
+                    info!(
+                        category,
+                        "Skipping subcategory that does not match the command"
+                    );
+                    skipped.push(category);
+                }
+            }
+        }
+        tree.mark_skipped(&skipped);
+        targets.sort();
+        targets.dedup();
+
+        (subcategories, targets)
+    }
 
-        let (replaced, is_changed) = self.replacers.replace_all(html).await.map_err(|err| {
-            warn!(message = "カテゴリの変更中にエラーが発生しました", err = ?err);
-            "カテゴリの変更中にエラーが発生しました".to_string()
+    /// 下位カテゴリの名前を置き換えた操作先が全て存在するか確かめる
+    async fn check_subcategory_targets(&self, targets: &[String]) -> Result<(), String> {
+        let missing = get_missing_pages(&self.bot, targets).await.map_err(|err| {
+            warn!(message = "下位カテゴリの操作先を確認できませんでした", err = ?err);
+            "下位カテゴリの操作先を確認できませんでした".to_string()
         })?;
+        if !missing.is_empty() {
+            return Err(format!(
+                "下位カテゴリの操作先{}が存在しません。作成してから再度依頼してください",
+                missing
+                    .iter()
+                    .map(|category| format!("[[:{category}]]"))
+                    .collect::<Vec<_>>()
+                    .join("、")
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// 実行後も移動元のカテゴリに残っているページを確認する.
+    /// カテゴリから外す操作でない場合や、保存していない場合は確認しない
+    async fn verify_remaining(
+        &self,
+        statuses: &IndexMap<String, OperationResult>,
+    ) -> IndexMap<String, RemainingReason> {
+        if !self.removes_from_category() || self.dry_run {
+            return IndexMap::new();
+        }
+
+        find_remaining_members(&self.bot, &self.from, self.namespaces.clone(), statuses)
+            .await
+            .unwrap_or_else(|err| {
+                warn!(message = "カテゴリに残っているページを確認できませんでした", err = ?err);
+                IndexMap::new()
+            })
+    }
+
+    /// 編集の要約. 保護されたページの編集依頼にも使う
+    pub fn summary(&self) -> &str {
+        &self.summary
+    }
 
-        if !is_changed {
-            return Ok(OperationStatus::Skipped);
+    /// 中断されたコマンド `id` として実行する
+    fn resume_as(&mut self, id: Ulid) {
+        self.summary = self.summary.replace(&self.id.to_string(), &id.to_string());
+        self.id = id;
+    }
+
+    /// 移動先のカテゴリページが既に存在しないか確かめる
+    async fn check_move_target(&self) -> Result<(), String> {
+        let to = self
+            .to
+            .first()
+            .ok_or_else(|| "移動先のカテゴリが指定されていません".to_string())?;
+
+        let exists = self
+            .bot
+            .page(to)
+            .map_err(|err| err.to_string())?
+            .exists()
+            .await
+            .map_err(|err| {
+                warn!(message = "移動先のカテゴリページを確認できませんでした", err = ?err);
+                "移動先のカテゴリページを確認できませんでした".to_string()
+            })?;
+        if exists {
+            return Err(format!(
+                "移動先の[[:{to}]]が既に存在するため、カテゴリページを移動できません。手作業で対応してから再度依頼してください"
+            ));
         }
 
-        self.save_page(page, replaced).await?;
+        Ok(())
+    }
+
+    /// `from` のカテゴリページを `to` へ移動し、移動元に{{Category redirect}}を残す.
+    /// 移動後に{{Category redirect}}を設置できなかった場合は `Ok(Some(message))` を返す
+    async fn move_category_page(&self) -> Result<Option<String>, String> {
+        let to = self
+            .to
+            .first()
+            .ok_or_else(|| "移動先のカテゴリが指定されていません".to_string())?;
+
+        if self.dry_run {
+            info!("No move was made due to dry-run");
+            return Ok(None);
+        }
+
+        // ページIDは移動しても変わらないため、移動前に取得して移動直後に記録できるようにする
+        let page_id = self
+            .bot
+            .page(&self.from)
+            .map_err(|err| err.to_string())?
+            .id()
+            .await
+            .ok()
+            .flatten()
+            .ok_or_else(|| "移動元のカテゴリページのIDを取得できませんでした".to_string())?;
+
+        move_page(&self.bot, &self.from, to, &self.summary, false)
+            .await
+            .map_err(|err| {
+                warn!(message = "カテゴリページの移動に失敗しました", err = ?err);
+                "カテゴリページの移動に失敗しました".to_string()
+            })?;
+
+        store_move_operation(&self.id, page_id, &self.from, to)
+            .await
+            .map_err(|err| {
+                warn!(message = "データベースへのオペレーション保存に失敗しました", err = ?err);
+                "カテゴリページは移動しましたが、データベースへのオペレーション保存に失敗しました"
+                    .to_string()
+            })?;
+
+        let redirect = format!(
+            "{{{{Category redirect|{}}}}}",
+            to.trim_start_matches("Category:")
+        );
+        let saved = match self.bot.page(&self.from) {
+            Ok(page) => page
+                .save(redirect, &SaveOptions::summary(&self.summary))
+                .await
+                .map(|_| ()),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = saved {
+            warn!(message = "移動元へのCategory redirectの設置に失敗しました", err = ?err);
+            return Ok(Some(
+                "カテゴリページは移動しましたが、移動元に{{Category redirect}}を設置できませんでした。手作業で設置してください"
+                    .to_string(),
+            ));
+        }
 
-        Ok(OperationStatus::Done)
+        Ok(None)
     }
 
-    async fn save_page<S>(&self, page: Page, edit: S) -> Result<Page, String>
+    /// 停止要求を受け取ったか緊急停止されている場合は `None` を返す.
+    /// `is_in_use_expired` が `true` の場合、使用中のページは後回しにせず編集を諦める
+    #[allow(clippy::too_many_arguments)]
+    async fn process_member(
+        &self,
+        page: Page,
+        category: &str,
+        replacers: &R,
+        rights: Option<&[String]>,
+        is_in_use_expired: bool,
+        save_limiter: &SaveLimiter,
+        shutdown: &ShutdownSignal,
+    ) -> Option<(String, OperationResult)> {
+        if shutdown.is_received() || is_emergency_stopped(&self.bot).await {
+            return None;
+        }
+
+        let title = page.title().to_string();
+        let result = if self.excluded.contains(&title) {
+            Ok(OperationStatus::Excluded)
+        } else {
+            self.process_page(page, category, replacers, rights, save_limiter)
+                .await
+        };
+        let result = match result {
+            Ok(OperationStatus::Deferred) if is_in_use_expired => Ok(OperationStatus::InUse),
+            result => result,
+        };
+
+        if !self.trial {
+            if let Err(err) = store_progress(&self.id, &title, &result).await {
+                warn!(message = "進捗をデータベースに保存できませんでした", title, err = ?err);
+            }
+            if result == Ok(OperationStatus::Deferred) {
+                if let Err(err) = store_deferred_page(&self.id, &title).await {
+                    warn!(message = "使用中のページをデータベースに保存できませんでした", title, err = ?err);
+                }
+            }
+        }
+        Some((title, result))
+    }
+
+    async fn process_page(
+        &self,
+        mut page: Page,
+        category: &str,
+        replacers: &R,
+        rights: Option<&[String]>,
+        save_limiter: &SaveLimiter,
+    ) -> OperationResult {
+        // 保護されていて編集できないページは置換も保存もしない.
+        // 権限を取得できなかった場合は保存時のエラーで判断する
+        if let Some(rights) = rights {
+            match get_page_info(&self.bot, page.title()).await {
+                Ok(info) if !can_edit(&info.protection, rights) => {
+                    return Ok(OperationStatus::Protected);
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    warn!(message = "ページの保護状態を取得できませんでした", title = page.title(), err = ?err);
+                }
+            }
+        }
+
+        for _ in 0..=EDIT_CONFLICT_MAX_RETRIES {
+            // 保存時には取得した版が基準版として渡される
+            let html = page.html().await.map_err(|err| {
+                warn!(message = "ページの取得中にエラーが発生しました", err = ?err);
+                "ページの取得中にエラーが発生しました".to_string()
+            })?;
+
+            // {{nobots}}などで拒否されているページや、使用中のページは編集しない
+            match find_marker(&html) {
+                Ok(Some(PageMarker::BotsDenied)) => return Ok(OperationStatus::BotsDenied),
+                Ok(Some(PageMarker::InUse)) => return Ok(OperationStatus::Deferred),
+                Ok(None) => {}
+                Err(err) => {
+                    warn!(message = "ページのテンプレートの確認中にエラーが発生しました", err = ?err);
+                    return Err("ページのテンプレートの確認中にエラーが発生しました".to_string());
+                }
+            }
+
+            let (replaced, changes) = replacers
+                .replace_all_in(page.namespace(), html.clone())
+                .instrument(info_span!("replace", title = page.title()))
+                .await
+                .map_err(|err| {
+                    warn!(message = "カテゴリの変更中にエラーが発生しました", err = ?err);
+                    "カテゴリの変更中にエラーが発生しました".to_string()
+                })?;
+
+            if changes.is_empty() {
+                return Ok(self.unchanged_status(&page, &html, category).await);
+            }
+
+            if self.trial {
+                return self
+                    .preview_page(&page, replaced)
+                    .await
+                    .map(|diff| OperationStatus::Previewed { diff, changes });
+            }
+
+            let title = page.title().to_string();
+            match self.save_page(page, replaced, &changes, save_limiter).await {
+                Ok(_) => return Ok(OperationStatus::Done),
+                Err(SaveError::EditConflict) => {
+                    info!(
+                        title,
+                        "Edit conflict detected. Retrying with the latest revision"
+                    );
+                    // 最新版を取得し直すため、基準版を持たない新しい `Page` を作る
+                    page = self.bot.page(&title).map_err(|err| err.to_string())?;
+                }
+                Err(SaveError::Protected) => return Ok(OperationStatus::Protected),
+                Err(SaveError::Other(message)) => return Err(message),
+            }
+        }
+
+        Ok(OperationStatus::EditConflicted)
+    }
+
+    /// カテゴリタグがなく置換しなかった場合の結果.
+    /// カテゴリから外す操作では、カテゴリを付与しているテンプレートを調べる
+    async fn unchanged_status(
+        &self,
+        page: &Page,
+        html: &ImmutableWikicode,
+        category: &str,
+    ) -> OperationStatus {
+        if !self.removes_from_category() {
+            return OperationStatus::Skipped;
+        }
+
+        match find_category_templates(&self.bot, page.title(), html, category).await {
+            Ok(templates) if !templates.is_empty() => {
+                OperationStatus::CategoryFromTemplate(templates)
+            }
+            Ok(_) => OperationStatus::Skipped,
+            Err(err) => {
+                warn!(message = "カテゴリを付与しているテンプレートを確認できませんでした", title = page.title(), err = ?err);
+                OperationStatus::Skipped
+            }
+        }
+    }
+
+    /// 移動元のカテゴリからページを外す操作か
+    fn removes_from_category(&self) -> bool {
+        matches!(
+            self.command_type,
+            CommandType::Reassignment | CommandType::Remove | CommandType::Move
+        )
+    }
+
+    /// 保存した場合の差分を返す
+    async fn preview_page(
+        &self,
+        page: &Page,
+        replaced: ImmutableWikicode,
+    ) -> Result<String, String> {
+        let before = page.wikitext().await.map_err(|err| {
+            warn!(message = "ページの取得中にエラーが発生しました", err = ?err);
+            "ページの取得中にエラーが発生しました".to_string()
+        })?;
+        let after = self
+            .bot
+            .parsoid()
+            .transform_to_wikitext(&replaced)
+            .await
+            .map_err(|err| {
+                warn!(message = "差分の作成中にエラーが発生しました", err = ?err);
+                "差分の作成中にエラーが発生しました".to_string()
+            })?;
+
+        Ok(line_diff(&before, &after))
+    }
+
+    async fn save_page<S>(
+        &self,
+        page: Page,
+        edit: S,
+        changes: &[ChangeRecord],
+        save_limiter: &SaveLimiter,
+    ) -> Result<Page, SaveError>
     where
         S: Into<ImmutableWikicode>,
     {
@@ -98,14 +840,24 @@ where
             return Ok(page);
         }
 
+        save_limiter.wait().await;
         let (page, res) = page
-            .save(edit.into(), &self.save_opts)
+            .save(
+                edit.into(),
+                &SaveOptions::summary(&summary_with_changes(&self.summary, changes)),
+            )
             .await
-            .map_err(|err| {
-                warn!(message = "ページの保存に失敗しました", err = ?err);
-                "ページの保存に失敗しました".to_string()
+            .map_err(|err| match err {
+                mwbot::Error::EditConflict => SaveError::EditConflict,
+                mwbot::Error::ProtectedPage => SaveError::Protected,
+                err => {
+                    warn!(message = "ページの保存に失敗しました", err = ?err);
+                    SaveError::Other("ページの保存に失敗しました".to_string())
+                }
             })?;
-        self.store_operation_to_db(res.pageid, res.newrevid).await?;
+        self.store_operation_to_db(res.pageid, res.newrevid, changes)
+            .await
+            .map_err(SaveError::Other)?;
 
         Ok(page)
     }
@@ -114,12 +866,13 @@ where
         &self,
         page_id: u32,
         new_rev_id: Option<u64>,
+        changes: &[ChangeRecord],
     ) -> Result<(), String> {
         let Some(new_rev_id) = new_rev_id else {
             return Err("新しい版のIDを取得できませんでした".to_string());
         };
 
-        store_operation(&self.id, page_id, new_rev_id)
+        store_operation(&self.id, page_id, new_rev_id, changes)
             .await
             .map_err(|err| {
                 warn!(message = "データベースへのオペレーション保存に失敗しました", err = ?err);
@@ -128,12 +881,37 @@ where
     }
 }
 
+/// 編集競合時に置換をやり直す回数
+const EDIT_CONFLICT_MAX_RETRIES: usize = 2;
+
+/// ページの保存に失敗した理由
+enum SaveError {
+    /// 取得してから保存するまでに他の編集があった
+    EditConflict,
+    /// 保護されていて編集できない
+    Protected,
+    Other(String),
+}
+
 #[derive(Debug)]
 pub enum CommandStatus {
-    EmergencyStopped,
+    /// 緊急停止した場合. 同じセクションを再実行すると続きから再開する
+    EmergencyStopped {
+        id: Ulid,
+    },
+    /// 停止要求を受け取り中断した場合. 同じセクションを再実行すると続きから再開する
+    Interrupted {
+        id: Ulid,
+    },
     Done {
         id: Ulid,
         statuses: IndexMap<String, OperationResult>,
+        /// 実行後も移動元のカテゴリに残っているページ
+        remaining: IndexMap<String, RemainingReason>,
+        /// たどった下位カテゴリ
+        tree: Option<CategoryTree>,
+        /// ダンプの時点と比べた所属ページの違い
+        dump_diff: Option<DumpDiff>,
     },
     /// Commandがエラーの場合
     Error {
@@ -142,14 +920,64 @@ pub enum CommandStatus {
         statuses: IndexMap<String, OperationResult>,
         message: String,
     },
+    /// 同じキューを既に処理していた場合
     Skipped,
     CategoryEmpty,
+    /// 試行モードで実行した場合
+    Trial {
+        id: Ulid,
+        statuses: IndexMap<String, OperationResult>,
+    },
+    /// 使用中のページを後回しにした場合. 次回以降の実行で使用中のページのみ再度処理する
+    Deferred {
+        id: Ulid,
+        statuses: IndexMap<String, OperationResult>,
+        /// 今回の実行で新たに後回しにしたページがあるか
+        is_newly_deferred: bool,
+    },
 }
 
 #[derive(Debug, PartialEq)]
 pub enum OperationStatus {
     Done,
     Skipped,
+    /// コマンドで除外が指定されたため操作しなかった
+    Excluded,
+    /// 試行モードのため保存せず、保存した場合の差分と変更内容を記録した
+    Previewed {
+        diff: String,
+        changes: Vec<ChangeRecord>,
+    },
+    /// 置換をやり直しても編集競合が解消しなかった
+    EditConflicted,
+    /// カテゴリタグがなく、テンプレートによってカテゴリが付与されているため変更できなかった
+    CategoryFromTemplate(Vec<String>),
+    /// 保護されていてBotの権限では編集できない
+    Protected,
+    /// {{nobots}}や{{bots}}によりBotの編集が拒否されている
+    BotsDenied,
+    /// {{使用中}}などが貼られているため、後で再度処理する
+    Deferred,
+    /// {{使用中}}などが貼られたまま期限を過ぎたため、編集を諦めた
+    InUse,
+}
+
+impl OperationStatus {
+    /// 完了報告にページごとに記載するメッセージ
+    pub fn report_message(&self) -> Option<Cow<'static, str>> {
+        match self {
+            Self::Done | Self::Skipped | Self::Previewed { .. } => None,
+            Self::Excluded => Some("除外しました".into()),
+            Self::EditConflicted => Some("編集競合のため保存できませんでした".into()),
+            Self::Protected => Some("保護されているため編集できませんでした".into()),
+            Self::BotsDenied => Some("Botによる編集が拒否されているため編集しませんでした".into()),
+            Self::Deferred => Some("使用中のため、後で再度編集します".into()),
+            Self::InUse => Some("使用中のまま期限を過ぎたため編集しませんでした".into()),
+            Self::CategoryFromTemplate(templates) => {
+                Some(format!("カテゴリは{}により付与されています", templates.join("、")).into())
+            }
+        }
+    }
 }
 
 pub type OperationResult = Result<OperationStatus, String>;
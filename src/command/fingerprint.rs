@@ -0,0 +1,149 @@
+use mwbot::parsoid::prelude::*;
+
+use crate::BOT_NAME;
+
+/// 依頼者の署名とみなすリンク先のプレフィックス
+const REQUESTER_LINK_PREFIXES: [&str; 5] = [
+    "利用者:",
+    "User:",
+    "利用者‐会話:",
+    "User talk:",
+    "特別:投稿記録/",
+];
+
+/// 処理済みのキューを識別するための、正規化したセクション名と依頼者の組
+#[derive(Debug, Clone, PartialEq)]
+pub struct SectionFingerprint {
+    pub heading: String,
+    /// 署名が見つからない場合は空文字列
+    pub requester: String,
+}
+
+impl SectionFingerprint {
+    pub fn new(section: &Section) -> Self {
+        let heading = section
+            .heading()
+            .map(|heading| normalize_heading(&heading.text_contents()))
+            .unwrap_or_default();
+        let requester = section
+            .filter_links()
+            .into_iter()
+            .filter_map(|link| requester_of(&link.target()))
+            .find(|requester| requester != BOT_NAME)
+            .unwrap_or_default();
+
+        Self { heading, requester }
+    }
+}
+
+/// 前後の空白を除き、連続する空白を1つにまとめる
+fn normalize_heading(heading: &str) -> String {
+    heading.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// 利用者ページや投稿記録へのリンク先から利用者名を取り出す
+fn requester_of(target: &str) -> Option<String> {
+    REQUESTER_LINK_PREFIXES.iter().find_map(|prefix| {
+        let name = target.strip_prefix(prefix)?;
+        // サブページや節へのリンクは利用者名のみにする
+        let name = name.split(['/', '#']).next().unwrap_or(name);
+        Some(name.replace('_', " "))
+    })
+}
+
+/// 処理済みのキューの再実行が依頼されているか.
+/// 最後の{{BOTREQ}}が{{BOTREQ|再実行}}である場合に再実行する
+pub fn is_rerun_requested(section: &Section) -> bool {
+    section
+        .filter_templates()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|template| template.name() == "Template:BOTREQ")
+        .last()
+        .is_some_and(|template| template.param("1").as_deref() == Some("再実行"))
+}
+
+#[cfg(test)]
+mod test {
+    use indoc::indoc;
+    use mwbot::parsoid::prelude::*;
+    use rstest::rstest;
+
+    use crate::command::fingerprint::{
+        is_rerun_requested,
+        normalize_heading,
+        requester_of,
+        SectionFingerprint,
+    };
+    use crate::util::test;
+
+    #[rstest]
+    #[case(
+        "Bot: Category:Name1をCategory:Name2へ",
+        "Bot: Category:Name1をCategory:Name2へ"
+    )]
+    #[case(
+        "  Bot:  Category:Name1を\tCategory:Name2へ ",
+        "Bot: Category:Name1を Category:Name2へ"
+    )]
+    fn test_normalize_heading(#[case] heading: &str, #[case] expected: &str) {
+        assert_eq!(normalize_heading(heading), expected);
+    }
+
+    #[rstest]
+    #[case("利用者:Example", Some("Example"))]
+    #[case("利用者‐会話:Example_User#節", Some("Example User"))]
+    #[case("特別:投稿記録/192.0.2.1", Some("192.0.2.1"))]
+    #[case("プロジェクト:カテゴリ関連/議論", None)]
+    fn test_requester_of(#[case] target: &str, #[case] expected: Option<&str>) {
+        assert_eq!(requester_of(target).as_deref(), expected);
+    }
+
+    #[rstest]
+    #[case(
+        indoc! {"\
+            == Bot: [[:Category:Name1]]を[[:Category:Name2]]へ ==
+            [[プロジェクト:カテゴリ関連/議論/yyyy年/mm月dd日#XYZ|議論]]を参照。 --[[User:Example|Example]] ([[User talk:Example|Talk]])
+            {{BOTREQ|不受理}} 不明なコマンドです --[[User:QueueBot|QueueBot]]
+        "},
+        false,
+    )]
+    #[case(
+        indoc! {"\
+            == Bot: [[:Category:Name1]]を[[:Category:Name2]]へ ==
+            [[プロジェクト:カテゴリ関連/議論/yyyy年/mm月dd日#XYZ|議論]]を参照。 --[[User:Example|Example]] ([[User talk:Example|Talk]])
+            {{BOTREQ|不受理}} 不明なコマンドです --[[User:QueueBot|QueueBot]]
+            {{BOTREQ|再実行}} 修正しました --[[User:Example|Example]]
+        "},
+        true,
+    )]
+    #[tokio::test]
+    async fn test_fingerprint(
+        #[case] wikitext: &str,
+        #[case] rerun_requested: bool,
+    ) -> anyhow::Result<()> {
+        let bot = test::bot().await;
+
+        let html = bot
+            .parsoid()
+            .transform_to_html(wikitext)
+            .await?
+            .into_mutable();
+        let sections = html.iter_sections();
+        let section = sections
+            .into_iter()
+            .find(|section| !section.is_pseudo_section())
+            .expect("could not get section");
+
+        assert_eq!(
+            SectionFingerprint::new(&section),
+            SectionFingerprint {
+                heading: "Bot: Category:Name1をCategory:Name2へ".to_string(),
+                requester: "Example".to_string(),
+            }
+        );
+        assert_eq!(is_rerun_requested(&section), rerun_requested);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,85 @@
+use mwbot::parsoid::prelude::*;
+
+use crate::BOT_NAME;
+
+/// 使用中であることを示すテンプレート
+const IN_USE_TEMPLATES: [&str; 4] = [
+    "Template:使用中",
+    "Template:工事中",
+    "Template:In use",
+    "Template:Inuse",
+];
+
+/// ページに貼られた、Botによる編集を控えるべきことを示すテンプレート
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PageMarker {
+    /// {{nobots}}や{{bots|deny=QueueBot}}によりBotの編集が拒否されている
+    BotsDenied,
+    /// {{使用中}}や{{工事中}}が貼られている
+    InUse,
+}
+
+/// ページに貼られたテンプレートを調べ、編集を控えるべき場合はその理由を返す
+pub fn find_marker(html: &ImmutableWikicode) -> anyhow::Result<Option<PageMarker>> {
+    let mut marker = None;
+    for template in html.clone().into_mutable().filter_templates()? {
+        let name = template.name();
+        let is_denied = match name.as_str() {
+            "Template:Nobots" => true,
+            "Template:Bots" => is_bot_denied(
+                template.param("allow").as_deref(),
+                template.param("deny").as_deref(),
+            ),
+            _ => false,
+        };
+        if is_denied {
+            // 編集の拒否は使用中よりも優先する
+            return Ok(Some(PageMarker::BotsDenied));
+        }
+
+        if IN_USE_TEMPLATES.contains(&name.as_str()) {
+            marker = Some(PageMarker::InUse);
+        }
+    }
+
+    Ok(marker)
+}
+
+/// {{bots}}の `allow` と `deny` から、このBotの編集が拒否されているかを判断する
+fn is_bot_denied(allow: Option<&str>, deny: Option<&str>) -> bool {
+    let contains_bot = |list: &str| {
+        list.split(',')
+            .map(str::trim)
+            .any(|name| name == "all" || name == BOT_NAME)
+    };
+
+    if let Some(allow) = allow {
+        return allow.trim() == "none" || !contains_bot(allow);
+    }
+    deny.is_some_and(contains_bot)
+}
+
+#[cfg(test)]
+mod test {
+    use rstest::rstest;
+
+    use crate::command::marker::is_bot_denied;
+
+    #[rstest]
+    #[case(None, None, false)]
+    #[case(None, Some("all"), true)]
+    #[case(None, Some("none"), false)]
+    #[case(None, Some("OtherBot, QueueBot"), true)]
+    #[case(None, Some("OtherBot"), false)]
+    #[case(Some("all"), None, false)]
+    #[case(Some("none"), None, true)]
+    #[case(Some("QueueBot"), None, false)]
+    #[case(Some("OtherBot"), None, true)]
+    fn test_is_bot_denied(
+        #[case] allow: Option<&str>,
+        #[case] deny: Option<&str>,
+        #[case] expected: bool,
+    ) {
+        assert_eq!(is_bot_denied(allow, deny), expected);
+    }
+}
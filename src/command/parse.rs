@@ -1,32 +1,131 @@
-use std::fmt::Debug;
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::sync::Arc;
 
 use anyhow::Context as _;
 use mwbot::parsoid::prelude::*;
-use mwbot::{Bot, SaveOptions};
+use mwbot::Bot;
 use ulid::Ulid;
 
+use crate::command::fingerprint::SectionFingerprint;
+use crate::command::SubcategoryReplacers;
+use crate::config::{ReplacerConfig, TemplateParameterRule};
 use crate::db::CommandType;
-use crate::replacer::{get_category_replacers, CategoryReplacerList};
+use crate::replacer::{
+    get_category_replacers,
+    get_sort_key_replacers,
+    CategoryReplacers,
+    ReplacerOptions,
+    ReplacerRegistry,
+    SortKeyRule,
+    TargetSortKeys,
+};
+use crate::title::CategoryNormalizer;
 
-pub type Command = super::Command<impl CategoryReplacerList + Debug>;
+pub type Command = super::Command<CategoryReplacers>;
+
+/// 保存せずに差分を投稿する試行モードのプレフィックス
+pub const TRIAL_PREFIX: &str = "Bot (試行):";
+
+/// 見出しの文字列が `Bot:` または `Bot (試行):` で始まるか. 先頭の空白は無視する
+pub fn is_bot_prefix(prefix: &str) -> bool {
+    let prefix = prefix.trim_start();
+    prefix.starts_with("Bot:") || prefix.starts_with(TRIAL_PREFIX)
+}
+
+/// コマンドのパースに失敗した理由
+#[derive(Debug, PartialEq)]
+pub enum ParseError {
+    /// `Bot:` などのプレフィックスが不明
+    UnknownPrefix(String),
+    /// プレフィックスで指定された名前空間が存在しない
+    UnknownNamespace(String),
+    /// `へ` や `を除去` などのサフィックスが不明
+    UnknownSuffix(String),
+    /// カテゴリへのリンクがあるべき位置にリンクがない
+    MissingCategoryLink,
+    /// リンク先がカテゴリではない
+    NotCategoryLink(String),
+    /// `を` や `と` などの区切りがない
+    MissingSeparator(&'static str),
+    /// 操作先のカテゴリが1つもない
+    NoTargets,
+    /// 操作先のカテゴリが [`TO_ITEMS_MAX_COUNT`] より多い
+    TooManyTargets(usize),
+    /// 移動先のカテゴリが複数指定されている
+    MultipleMoveTargets,
+    /// ソートキーの変更規則がない
+    MissingSortKeyRule,
+    /// `無効化:` や `有効化:` で指定された置換処理が存在しない
+    UnknownReplacer(String),
+    /// 下位カテゴリをたどれない操作で、下位カテゴリが指定されている
+    SubcategoryNotSupported,
+    /// `個別ソートキー:` で指定されたカテゴリが操作先に含まれない
+    UnknownSortKeyTarget(String),
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownPrefix(prefix) => write!(f, "不明なプレフィックスです: 「{prefix}」"),
+            Self::UnknownNamespace(namespace) => {
+                write!(f, "不明な名前空間です: 「{namespace}」")
+            }
+            Self::UnknownSuffix(suffix) => write!(f, "不明なコマンドです: 「{suffix}」"),
+            Self::MissingCategoryLink => write!(f, "カテゴリへのリンクがありません"),
+            Self::NotCategoryLink(target) => write!(f, "{target} はカテゴリではありません"),
+            Self::MissingSeparator(separator) => {
+                write!(f, "区切りの「{separator}」がありません")
+            }
+            Self::NoTargets => write!(f, "操作先のカテゴリが指定されていません"),
+            Self::TooManyTargets(count) => write!(
+                f,
+                "操作先のカテゴリが多すぎます ({count}件指定されています。最大{TO_ITEMS_MAX_COUNT}件まで指定できます)"
+            ),
+            Self::MultipleMoveTargets => write!(f, "移動先のカテゴリは1つのみ指定できます"),
+            Self::MissingSortKeyRule => write!(f, "ソートキーの変更規則が指定されていません"),
+            Self::UnknownReplacer(name) => write!(f, "不明な置換処理です: 「{name}」"),
+            Self::SubcategoryNotSupported => {
+                write!(f, "下位カテゴリは付け替え・複製・移動でのみ指定できます")
+            }
+            Self::UnknownSortKeyTarget(target) => {
+                write!(f, "個別ソートキーの対象 {target} は操作先に含まれていません")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
 
 pub struct Parser {
     bot: Bot,
     prefix: String,
     suffix: String,
     nodes: Vec<Wikinode>,
+    fingerprint: SectionFingerprint,
+    body: String,
+    excluded: Vec<String>,
+    subcategory_depth: usize,
+    keep_sort_key: bool,
+    /// `個別ソートキー:` で指定された操作先とソートキー
+    target_sort_keys: Vec<(String, String)>,
     discussion_link: String,
     dry_run: bool,
+    trial: bool,
+    normalizer: CategoryNormalizer,
+    template_rules: Vec<TemplateParameterRule>,
+    /// 設定で有効にされている置換処理
+    replacers: ReplacerConfig,
+    /// 本文で有効(`true`)・無効(`false`)が指定された置換処理
+    replacer_switches: Vec<(String, bool)>,
 }
 
 impl Parser {
     pub fn new(bot: Bot, section: &Section, dry_run: bool) -> anyhow::Result<Self> {
-        let nodes = section
+        let heading = section
             .heading()
-            .context("heading must not be pseudo section")?
-            .descendants()
-            .skip(1)
-            .collect::<Vec<_>>();
+            .context("heading must not be pseudo section")?;
+        let nodes = heading.descendants().skip(1).collect::<Vec<_>>();
 
         let prefix = nodes
             .first()
@@ -35,6 +134,11 @@ impl Parser {
             .context("コマンドのプレフィックスは文字列である必要があります")?
             .borrow()
             .to_string();
+        // `Bot (試行):` の場合は `Bot:` と同様に扱い、試行モードとする
+        let (prefix, trial) = match prefix.trim_start().strip_prefix(TRIAL_PREFIX) {
+            Some(rest) => (format!("Bot:{rest}"), true),
+            None => (prefix, false),
+        };
         let suffix = nodes
             .last()
             .context("コマンドのサフィックスが取得できませんんでした")?
@@ -42,41 +146,80 @@ impl Parser {
             .context("コマンドのサフィックスは文字列である必要があります")?
             .borrow()
             .to_string();
+        let excluded = collect_excluded_pages(section);
+        let normalizer = CategoryNormalizer::new(&bot);
         let discussion_link = section
             .filter_links()
             .into_iter()
-            .find(|link| !link.target().starts_with("Category:"))
-            .context("議論場所へのリンクがありません")?
-            .target();
+            .map(|link| link.target())
+            .find(|target| normalizer.normalize(target).is_none() && !excluded.contains(target))
+            .context("議論場所へのリンクがありません")?;
+        let body = section.text_contents();
+        let subcategory_depth = body.lines().find_map(parse_subcategory_depth).unwrap_or(0);
+        let keep_sort_key = body.lines().any(is_keep_sort_key);
+        let target_sort_keys = body.lines().filter_map(parse_target_sort_key).collect();
+        let replacer_switches = body.lines().flat_map(parse_replacer_switches).collect();
 
         Ok(Self {
             bot,
             prefix,
             suffix,
             nodes,
+            fingerprint: SectionFingerprint::new(section),
+            body,
+            excluded,
+            subcategory_depth,
+            keep_sort_key,
+            target_sort_keys,
             discussion_link,
             dry_run,
+            trial,
+            normalizer,
+            template_rules: Vec::new(),
+            replacers: ReplacerConfig::default(),
+            replacer_switches,
         })
     }
 
-    pub fn parse(self) -> Option<Command> {
-        self.parse_reassignment()
-            .or_else(|| self.parse_duplicate())
-            .or_else(|| self.parse_remove())
+    /// 設定で有効にされている置換処理を使う. 本文の `有効化:` や `無効化:` で変えられる
+    pub fn replacers(mut self, config: ReplacerConfig) -> Self {
+        self.replacers = config;
+        self
     }
 
-    fn parse_reassignment(&self) -> Option<Command> {
-        let namespaces = parse_prefix_namespaces(&self.prefix)?;
-        if self.suffix != "へ" {
-            return None;
+    /// Wiki上の設定で定義された、テンプレートの引数の置換規則を使う
+    pub fn template_rules(mut self, rules: Vec<TemplateParameterRule>) -> Self {
+        self.template_rules = rules;
+        self
+    }
+
+    pub fn parse(self) -> Result<Command, ParseError> {
+        let namespaces = parse_prefix_namespaces(&self.bot, &self.prefix)?;
+        // プレフィックスとサフィックスの間
+        let nodes = self.nodes.get(1..self.nodes.len() - 1).unwrap_or_default();
+
+        match self.suffix.trim() {
+            "へ" => self.parse_reassignment(namespaces, nodes),
+            "に複製" => self.parse_duplicate(namespaces, nodes),
+            "を除去" => self.parse_remove(namespaces, nodes),
+            "へ移動" => self.parse_move(namespaces, nodes),
+            "のソートキーを変更" => self.parse_sort_key(namespaces, nodes),
+            suffix => Err(ParseError::UnknownSuffix(suffix.to_string())),
         }
+    }
 
-        let nodes = self.nodes.get(1..self.nodes.len() - 1)?;
-        let (from, to) = collect_from_to(nodes)?;
+    fn parse_reassignment(
+        &self,
+        namespaces: Vec<u32>,
+        nodes: &[Wikinode],
+    ) -> Result<Command, ParseError> {
+        let (from, to, sort_keys) = collect_from_to(&self.normalizer, nodes)?;
 
         let id = Ulid::new();
-        let replacers = get_category_replacers(self.bot.clone(), from.clone(), to.clone());
-        let save_opts = SaveOptions::summary(&format!(
+        let options = self.replacer_options(&to, sort_keys)?;
+        let subcategory_replacers = self.subcategory_replacers(&from, &to, &options);
+        let replacers = get_category_replacers(self.bot.clone(), from.clone(), to.clone(), options);
+        let summary = format!(
             "BOT: [[:{}]]から{}へ変更 ([[{}|議論場所]]) (ID: {})",
             &from,
             &to.iter()
@@ -85,35 +228,41 @@ impl Parser {
                 .join(","),
             &self.discussion_link,
             &id,
-        ));
+        );
 
-        Some(Command {
+        Ok(Command {
             bot: self.bot.clone(),
-            dry_run: self.dry_run,
+            dry_run: self.dry_run || self.trial,
+            trial: self.trial,
             id,
             from,
             to,
             discussion_link: self.discussion_link.clone(),
             namespaces,
+            excluded: self.excluded.clone(),
+            subcategory_depth: self.subcategory_depth,
+            subcategory_replacers,
+            fingerprint: self.fingerprint.clone(),
             replacers,
-            save_opts,
+            summary,
             command_type: CommandType::Reassignment,
         })
     }
 
-    fn parse_duplicate(&self) -> Option<Command> {
-        let namespaces = parse_prefix_namespaces(&self.prefix)?;
-        if self.suffix != "に複製" {
-            return None;
-        }
-
-        let nodes = self.nodes.get(1..self.nodes.len() - 1)?;
-        let (source, mut dest) = collect_from_to(nodes)?;
+    fn parse_duplicate(
+        &self,
+        namespaces: Vec<u32>,
+        nodes: &[Wikinode],
+    ) -> Result<Command, ParseError> {
+        let (source, mut dest, sort_keys) = collect_from_to(&self.normalizer, nodes)?;
         dest.push(source.clone());
 
         let id = Ulid::new();
-        let replacers = get_category_replacers(self.bot.clone(), source.clone(), dest.clone());
-        let save_opts = SaveOptions::summary(&format!(
+        let options = self.replacer_options(&dest, sort_keys)?;
+        let subcategory_replacers = self.subcategory_replacers(&source, &dest, &options);
+        let replacers =
+            get_category_replacers(self.bot.clone(), source.clone(), dest.clone(), options);
+        let summary = format!(
             "BOT: [[:{}]]を{}へ複製 ([[{}|議論場所]]) (ID: {})",
             &source,
             &dest
@@ -123,117 +272,451 @@ impl Parser {
                 .join(","),
             &self.discussion_link,
             &id,
-        ));
+        );
 
-        Some(Command {
+        Ok(Command {
             bot: self.bot.clone(),
-            dry_run: self.dry_run,
+            dry_run: self.dry_run || self.trial,
+            trial: self.trial,
             id,
             from: source,
             to: dest,
             discussion_link: self.discussion_link.clone(),
             namespaces,
+            excluded: self.excluded.clone(),
+            subcategory_depth: self.subcategory_depth,
+            subcategory_replacers,
+            fingerprint: self.fingerprint.clone(),
             replacers,
-            save_opts,
+            summary,
             command_type: CommandType::Duplicate,
         })
     }
 
-    fn parse_remove(&self) -> Option<Command> {
-        let namespaces = parse_prefix_namespaces(&self.prefix)?;
-        if self.suffix != "を除去" {
-            return None;
-        }
-
-        let nodes = self.nodes.get(1..self.nodes.len() - 1)?;
-        let category = nodes.first()?.as_wikilink()?.target();
-        if !category.starts_with("Category:") {
-            return None;
+    fn parse_remove(
+        &self,
+        namespaces: Vec<u32>,
+        nodes: &[Wikinode],
+    ) -> Result<Command, ParseError> {
+        let category = category_link(&self.normalizer, nodes.first())?;
+        if self.subcategory_depth > 0 {
+            return Err(ParseError::SubcategoryNotSupported);
         }
 
         let id = Ulid::new();
-        let replacers = get_category_replacers(self.bot.clone(), category.clone(), vec![]);
-        let save_opts = SaveOptions::summary(&format!(
+        let replacers = get_category_replacers(
+            self.bot.clone(),
+            category.clone(),
+            vec![],
+            self.replacer_options(&[], HashMap::new())?,
+        );
+        let summary = format!(
             "BOT: [[:{}]]を除去 ([[{}|議論場所]]) (ID: {})",
             &category, &self.discussion_link, &id,
-        ));
+        );
 
-        Some(Command {
+        Ok(Command {
             bot: self.bot.clone(),
-            dry_run: self.dry_run,
+            dry_run: self.dry_run || self.trial,
+            trial: self.trial,
             id,
             from: category,
             to: vec![],
             discussion_link: self.discussion_link.clone(),
             namespaces,
+            excluded: self.excluded.clone(),
+            subcategory_depth: self.subcategory_depth,
+            subcategory_replacers: None,
+            fingerprint: self.fingerprint.clone(),
             replacers,
-            save_opts,
+            summary,
             command_type: CommandType::Remove,
         })
     }
-}
 
-fn parse_prefix_namespaces(prefix: &str) -> Option<Vec<u32>> {
-    match prefix.trim() {
-        "Bot:" => Some(vec![0, 14]),
-        "Bot: (記事)" => Some(vec![0]),
-        "Bot: (カテゴリ)" => Some(vec![14]),
-        _ => None,
+    fn parse_move(&self, namespaces: Vec<u32>, nodes: &[Wikinode]) -> Result<Command, ParseError> {
+        let (from, to, sort_keys) = collect_from_to(&self.normalizer, nodes)?;
+        // 移動先は1つのみ
+        if to.len() != 1 {
+            return Err(ParseError::MultipleMoveTargets);
+        }
+
+        let id = Ulid::new();
+        let options = self.replacer_options(&to, sort_keys)?;
+        let subcategory_replacers = self.subcategory_replacers(&from, &to, &options);
+        let replacers = get_category_replacers(self.bot.clone(), from.clone(), to.clone(), options);
+        let summary = format!(
+            "BOT: [[:{}]]を[[:{}]]へ移動 ([[{}|議論場所]]) (ID: {})",
+            &from, &to[0], &self.discussion_link, &id,
+        );
+
+        Ok(Command {
+            bot: self.bot.clone(),
+            dry_run: self.dry_run || self.trial,
+            trial: self.trial,
+            id,
+            from,
+            to,
+            discussion_link: self.discussion_link.clone(),
+            namespaces,
+            excluded: self.excluded.clone(),
+            subcategory_depth: self.subcategory_depth,
+            subcategory_replacers,
+            fingerprint: self.fingerprint.clone(),
+            replacers,
+            summary,
+            command_type: CommandType::Move,
+        })
+    }
+
+    fn parse_sort_key(
+        &self,
+        namespaces: Vec<u32>,
+        nodes: &[Wikinode],
+    ) -> Result<Command, ParseError> {
+        let category = category_link(&self.normalizer, nodes.first())?;
+        let rule = self
+            .body
+            .lines()
+            .find_map(SortKeyRule::parse)
+            .ok_or(ParseError::MissingSortKeyRule)?;
+        if self.subcategory_depth > 0 {
+            return Err(ParseError::SubcategoryNotSupported);
+        }
+
+        let id = Ulid::new();
+        let replacers = get_sort_key_replacers(
+            self.bot.clone(),
+            category.clone(),
+            rule,
+            self.replacer_options(&[], HashMap::new())?,
+        );
+        let summary = format!(
+            "BOT: [[:{}]]のソートキーを変更 ([[{}|議論場所]]) (ID: {})",
+            &category, &self.discussion_link, &id,
+        );
+
+        Ok(Command {
+            bot: self.bot.clone(),
+            dry_run: self.dry_run || self.trial,
+            trial: self.trial,
+            id,
+            from: category,
+            to: vec![],
+            discussion_link: self.discussion_link.clone(),
+            namespaces,
+            excluded: self.excluded.clone(),
+            subcategory_depth: self.subcategory_depth,
+            subcategory_replacers: None,
+            fingerprint: self.fingerprint.clone(),
+            replacers,
+            summary,
+            command_type: CommandType::SortKey,
+        })
+    }
+
+    /// 下位カテゴリの名前の `from` の部分を `to` に置き換え、同じ操作を適用する置換処理.
+    /// 下位カテゴリが指定されていない場合は `None` を返す
+    fn subcategory_replacers(
+        &self,
+        from: &str,
+        to: &[String],
+        options: &ReplacerOptions,
+    ) -> Option<SubcategoryReplacers<CategoryReplacers>> {
+        if self.subcategory_depth == 0 {
+            return None;
+        }
+
+        let bot = self.bot.clone();
+        let from = from.to_string();
+        let to = to.to_vec();
+        let options = options.clone();
+        Some(Arc::new(move |subcategory: &str| {
+            let renamed = to
+                .iter()
+                .map(|to| rename_subcategory(&from, to, subcategory))
+                .collect::<Option<Vec<_>>>()?;
+            let replacers = get_category_replacers(
+                bot.clone(),
+                subcategory.to_string(),
+                renamed.clone(),
+                options.clone(),
+            );
+            Some((renamed, replacers))
+        }))
+    }
+
+    /// 置換の設定. 操作先のリンクの表示文字列で指定されたソートキーと、
+    /// 本文の `個別ソートキー:` や `元のソートキー: 維持`、`無効化:` の指定に従う.
+    /// 同じ操作先に両方が指定された場合は `個別ソートキー:` を優先する
+    fn replacer_options(
+        &self,
+        to: &[String],
+        mut sort_keys: HashMap<String, String>,
+    ) -> Result<ReplacerOptions, ParseError> {
+        for (target, sort_key) in &self.target_sort_keys {
+            let category = self
+                .normalizer
+                .normalize_name(target)
+                .filter(|category| to.contains(category))
+                .ok_or_else(|| ParseError::UnknownSortKeyTarget(target.clone()))?;
+            sort_keys.insert(category, sort_key.clone());
+        }
+
+        let registry = ReplacerRegistry::default();
+        if let Some((name, _)) = self
+            .replacer_switches
+            .iter()
+            .find(|(name, _)| !registry.contains(name))
+        {
+            return Err(ParseError::UnknownReplacer(name.clone()));
+        }
+
+        Ok(ReplacerOptions {
+            sort_keys: TargetSortKeys::new(sort_keys, self.keep_sort_key),
+            template_rules: self.template_rules.clone(),
+            replacers: self.replacers.switched(&self.replacer_switches),
+        })
     }
 }
 
-const TO_ITEMS_MAX_COUNT: usize = 5;
+/// `除外:` に続く箇条書きのリンク先を、操作から除外するページとして集める
+fn collect_excluded_pages(section: &Section) -> Vec<String> {
+    section
+        .select("ul")
+        .into_iter()
+        .filter(|list| {
+            list.preceding_siblings()
+                .find(|sibling| sibling.as_element().is_some())
+                .is_some_and(|sibling| matches!(sibling.text_contents().trim(), "除外:" | "除外："))
+        })
+        .flat_map(|list| list.filter_links())
+        .map(|link| link.target())
+        .collect()
+}
 
-fn collect_from_to(nodes: &[Wikinode]) -> Option<(String, Vec<String>)> {
-    let from = nodes.first()?.as_wikilink()?.target();
-    if !from.starts_with("Category:") {
-        return None;
+/// 下位カテゴリ `subcategory` の名前に含まれる `from` のカテゴリ名を `to` のカテゴリ名に置き換える.
+/// 含まれない場合は `None` を返す
+fn rename_subcategory(from: &str, to: &str, subcategory: &str) -> Option<String> {
+    let from = from.trim_start_matches("Category:");
+    let to = to.trim_start_matches("Category:");
+    let name = subcategory.trim_start_matches("Category:");
+
+    name.contains(from)
+        .then(|| format!("Category:{}", name.replacen(from, to, 1)))
+}
+
+/// `下位カテゴリ: 2階層` 形式の行から、たどる下位カテゴリの深さを読み取る
+fn parse_subcategory_depth(line: &str) -> Option<usize> {
+    let depth = line
+        .trim()
+        .strip_prefix("下位カテゴリ")?
+        .trim_start()
+        .strip_prefix([':', '：'])?
+        .trim();
+    let depth = depth.strip_suffix("階層").unwrap_or(depth).trim();
+
+    depth.parse().ok()
+}
+
+/// `元のソートキー: 維持` の行であるか. 付け替え先の全てに元のソートキーを引き継ぐ.
+/// ソートキー変更の `ソートキー:` の行とは区別する
+fn is_keep_sort_key(line: &str) -> bool {
+    line.trim()
+        .strip_prefix("元のソートキー")
+        .map(str::trim_start)
+        .and_then(|rest| rest.strip_prefix([':', '：']))
+        .is_some_and(|rest| rest.trim() == "維持")
+}
+
+/// `個別ソートキー: Category:名前 = キー` 形式の行から、操作先とそのソートキーを読み取る
+fn parse_target_sort_key(line: &str) -> Option<(String, String)> {
+    let (target, sort_key) = line
+        .trim()
+        .strip_prefix("個別ソートキー")?
+        .trim_start()
+        .strip_prefix([':', '：'])?
+        .split_once(['=', '＝'])?;
+    let (target, sort_key) = (target.trim(), sort_key.trim());
+
+    (!target.is_empty() && !sort_key.is_empty()).then(|| (target.to_string(), sort_key.to_string()))
+}
+
+/// `無効化: image_requested, recursion` や `有効化: image_requested` 形式の行から、
+/// 置換処理の名前と有効にするかを読み取る
+fn parse_replacer_switches(line: &str) -> Vec<(String, bool)> {
+    let line = line.trim();
+    let (names, is_enabled) = if let Some(names) = line.strip_prefix("無効化") {
+        (names, false)
+    } else if let Some(names) = line.strip_prefix("有効化") {
+        (names, true)
+    } else {
+        return Vec::new();
+    };
+    let Some(names) = names.trim_start().strip_prefix([':', '：']) else {
+        return Vec::new();
+    };
+
+    names
+        .split([',', '、', '，'])
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| (name.to_string(), is_enabled))
+        .collect()
+}
+
+/// `Bot:` は記事とカテゴリ、`Bot: (テンプレート, ファイル)` のように括弧内で指定された場合はその名前空間を対象とする
+fn parse_prefix_namespaces(bot: &Bot, prefix: &str) -> Result<Vec<u32>, ParseError> {
+    let prefix = prefix.trim();
+    if prefix == "Bot:" {
+        return Ok(vec![0, 14]);
     }
 
-    let nodes = nodes.get(2..)?;
-    let separator = nodes.first()?.as_text()?;
-    if separator.borrow().trim() != "を" {
+    let names = prefix
+        .strip_prefix("Bot:")
+        .map(str::trim)
+        .and_then(|names| names.strip_prefix(['(', '（']))
+        .and_then(|names| names.strip_suffix([')', '）']))
+        .ok_or_else(|| ParseError::UnknownPrefix(prefix.to_string()))?;
+
+    let mut namespaces = Vec::new();
+    for name in names.split([',', '、', '，']).map(str::trim) {
+        let namespace = resolve_namespace(bot, name)
+            .ok_or_else(|| ParseError::UnknownNamespace(name.to_string()))?;
+        if !namespaces.contains(&namespace) {
+            namespaces.push(namespace);
+        }
+    }
+
+    Ok(namespaces)
+}
+
+/// 名前空間名または別名から名前空間IDを引く.
+/// 標準名前空間は名前を持たないため `記事` または `標準` で指定する.
+fn resolve_namespace(bot: &Bot, name: &str) -> Option<u32> {
+    if name == "記事" || name == "標準" {
+        return Some(0);
+    }
+    if name.is_empty() {
         return None;
     }
 
-    let nodes = nodes.get(1..)?;
+    // TitleCodecはsiteinfoの名前空間と別名を元に名前空間を解決する
+    let namespace = bot
+        .title_codec()
+        .new_title(&format!("{name}:Dummy"))
+        .ok()?
+        .namespace();
+    // 特別・メディア名前空間(負のID)や、名前空間として解決されなかった場合は不明とする
+    u32::try_from(namespace)
+        .ok()
+        .filter(|namespace| *namespace != 0)
+}
+
+/// カテゴリへのリンクであれば、正規化したリンク先を返す
+fn category_link(
+    normalizer: &CategoryNormalizer,
+    node: Option<&Wikinode>,
+) -> Result<String, ParseError> {
+    let target = node
+        .and_then(|node| node.as_wikilink())
+        .ok_or(ParseError::MissingCategoryLink)?
+        .target();
+
+    normalizer
+        .normalize(&target)
+        .ok_or(ParseError::NotCategoryLink(target))
+}
+
+/// [`category_link`] に加えて、`[[:Category:名前|キー]]` のように表示文字列がリンク先と異なる場合はソートキーとして返す
+fn category_link_with_sort_key(
+    normalizer: &CategoryNormalizer,
+    node: Option<&Wikinode>,
+) -> Result<(String, Option<String>), ParseError> {
+    let category = category_link(normalizer, node)?;
+    let text = node.map(|node| node.text_contents()).unwrap_or_default();
+    let text = text.trim();
+    let sort_key = (!text.is_empty()
+        && normalizer.normalize_name(text).as_ref() != Some(&category))
+    .then(|| text.to_string());
+
+    Ok((category, sort_key))
+}
+
+/// 区切りの文字列であるか確認する
+fn separator(node: Option<&Wikinode>, separator: &'static str) -> Result<(), ParseError> {
+    match node.and_then(|node| node.as_text()) {
+        Some(text) if text.borrow().trim() == separator => Ok(()),
+        _ => Err(ParseError::MissingSeparator(separator)),
+    }
+}
+
+const TO_ITEMS_MAX_COUNT: usize = 5;
+
+/// 操作元のカテゴリ、操作先のカテゴリ、操作先ごとに指定されたソートキー
+type FromTo = (String, Vec<String>, HashMap<String, String>);
+
+fn collect_from_to(
+    normalizer: &CategoryNormalizer,
+    nodes: &[Wikinode],
+) -> Result<FromTo, ParseError> {
+    let from = category_link(normalizer, nodes.first())?;
+
+    let nodes = nodes.get(2..).unwrap_or_default();
+    separator(nodes.first(), "を")?;
+
+    let nodes = nodes.get(1..).unwrap_or_default();
+    let mut sort_keys = HashMap::new();
     let to = nodes
         // ["カテゴリ名", "と"]で区切る
         // リンクの後に表示文字列が続くので3つずつ区切る
         .chunks(3)
         .map(|chunk| {
-            if chunk.len() != 3 {
-                Some(chunk.first()?.as_wikilink()?.target())
-            } else {
-                let category = chunk.first()?.as_wikilink()?.target();
-                if !category.starts_with("Category:") {
-                    return None;
-                }
-                let separator = chunk.get(2)?.as_text()?;
-                if separator.borrow().trim() != "と" {
-                    return None;
-                }
-
-                Some(category)
+            let (category, sort_key) = category_link_with_sort_key(normalizer, chunk.first())?;
+            if chunk.len() == 3 {
+                separator(chunk.get(2), "と")?;
             }
+            if let Some(sort_key) = sort_key {
+                sort_keys.insert(category.clone(), sort_key);
+            }
+
+            Ok(category)
         })
-        .collect::<Option<Vec<_>>>()?;
+        .collect::<Result<Vec<_>, _>>()?;
 
-    if to.is_empty() || to.len() > TO_ITEMS_MAX_COUNT {
-        return None;
+    if to.is_empty() {
+        return Err(ParseError::NoTargets);
+    }
+    if to.len() > TO_ITEMS_MAX_COUNT {
+        return Err(ParseError::TooManyTargets(to.len()));
     }
 
-    Some((from, to))
+    Ok((from, to, sort_keys))
 }
 
 #[cfg(test)]
 mod test {
+    use std::collections::HashMap;
+
     use indoc::indoc;
     use mwbot::parsoid::prelude::*;
     use rstest::rstest;
 
-    use crate::command::parse::Parser;
+    use crate::command::fingerprint::SectionFingerprint;
+    use crate::command::parse::{
+        collect_from_to,
+        is_bot_prefix,
+        is_keep_sort_key,
+        parse_replacer_switches,
+        parse_subcategory_depth,
+        parse_target_sort_key,
+        rename_subcategory,
+        ParseError,
+        Parser,
+    };
     use crate::db::CommandType;
+    use crate::replacer::{CategoryReplacerList, TargetSortKeys};
+    use crate::title::CategoryNormalizer;
     use crate::util::test;
 
     /// コマンドが正常にパースできることを確認するテスト.
@@ -490,6 +973,66 @@ mod test {
     & [14],
     CommandType::Remove,
     )]
+    // ========== 移動 (全名前空間) ==========
+    #[case(
+    indoc ! {"\
+            == Bot: [[:Category:Name1]]を[[:Category:Name2]]へ移動 ==
+            [[プロジェクト:カテゴリ関連/議論/yyyy年/mm月dd日#XYZ|議論]]を参照。 --[[User:Example|Example]] ([[User talk:Example|Talk]])
+        "},
+    "Category:Name1",
+    & ["Category:Name2"],
+    "プロジェクト:カテゴリ関連/議論/yyyy年/mm月dd日#XYZ",
+    & [0, 14],
+    CommandType::Move,
+    )]
+    // ========== 移動 (記事) ==========
+    #[case(
+    indoc ! {"\
+            == Bot: (記事) [[:Category:Name1]]を[[:Category:Name2]]へ移動 ==
+            [[プロジェクト:カテゴリ関連/議論/yyyy年/mm月dd日#XYZ|議論]]を参照。 --[[User:Example|Example]] ([[User talk:Example|Talk]])
+        "},
+    "Category:Name1",
+    & ["Category:Name2"],
+    "プロジェクト:カテゴリ関連/議論/yyyy年/mm月dd日#XYZ",
+    & [0],
+    CommandType::Move,
+    )]
+    // ========== ソートキー変更 ==========
+    #[case(
+    indoc ! {"\
+            == Bot: [[:Category:Name1]]のソートキーを変更 ==
+            ソートキー: DEFAULTSORT
+            [[プロジェクト:カテゴリ関連/議論/yyyy年/mm月dd日#XYZ|議論]]を参照。 --[[User:Example|Example]] ([[User talk:Example|Talk]])
+        "},
+    "Category:Name1",
+    & [],
+    "プロジェクト:カテゴリ関連/議論/yyyy年/mm月dd日#XYZ",
+    & [0, 14],
+    CommandType::SortKey,
+    )]
+    // ========== 再配属 (任意の名前空間) ==========
+    #[case(
+    indoc ! {"\
+            == Bot: (テンプレート, ファイル) [[:Category:Name1]]を[[:Category:Name2]]へ ==
+            [[プロジェクト:カテゴリ関連/議論/yyyy年/mm月dd日#XYZ|議論]]を参照。 --[[User:Example|Example]] ([[User talk:Example|Talk]])
+        "},
+    "Category:Name1",
+    & ["Category:Name2"],
+    "プロジェクト:カテゴリ関連/議論/yyyy年/mm月dd日#XYZ",
+    & [10, 6],
+    CommandType::Reassignment,
+    )]
+    #[case(
+    indoc ! {"\
+            == Bot: (記事、Portal、Wikipedia) [[:Category:Name1]]を[[:Category:Name2]]へ ==
+            [[プロジェクト:カテゴリ関連/議論/yyyy年/mm月dd日#XYZ|議論]]を参照。 --[[User:Example|Example]] ([[User talk:Example|Talk]])
+        "},
+    "Category:Name1",
+    & ["Category:Name2"],
+    "プロジェクト:カテゴリ関連/議論/yyyy年/mm月dd日#XYZ",
+    & [0, 100, 4],
+    CommandType::Reassignment,
+    )]
     #[tokio::test]
     async fn test_parse_success(
         #[case] wikitext: &str,
@@ -521,7 +1064,393 @@ mod test {
         assert_eq!(command.discussion_link, discussion_link);
         assert_eq!(command.namespaces, namespaces);
         assert_eq!(command.command_type, command_type);
+        // 中断したコマンドは見出しと依頼者の組で特定する
+        assert_eq!(command.fingerprint, SectionFingerprint::new(&section));
+        assert_eq!(command.fingerprint.requester, "Example");
+
+        Ok(())
+    }
+
+    /// 不正なコマンドが理由付きで失敗することを確認するテスト.
+    #[rstest]
+    #[case(
+    indoc ! {"\
+            == Bot: 記事 [[:Category:Name1]]を[[:Category:Name2]]へ ==
+            [[プロジェクト:カテゴリ関連/議論/yyyy年/mm月dd日#XYZ|議論]]を参照。 --[[User:Example|Example]] ([[User talk:Example|Talk]])
+        "},
+    ParseError::UnknownPrefix("Bot: 記事".to_string()),
+    )]
+    #[case(
+    indoc ! {"\
+            == Bot: (存在しない名前空間) [[:Category:Name1]]を[[:Category:Name2]]へ ==
+            [[プロジェクト:カテゴリ関連/議論/yyyy年/mm月dd日#XYZ|議論]]を参照。 --[[User:Example|Example]] ([[User talk:Example|Talk]])
+        "},
+    ParseError::UnknownNamespace("存在しない名前空間".to_string()),
+    )]
+    #[case(
+    indoc ! {"\
+            == Bot: [[:Category:Name1]]を[[:Category:Name2]]に変更 ==
+            [[プロジェクト:カテゴリ関連/議論/yyyy年/mm月dd日#XYZ|議論]]を参照。 --[[User:Example|Example]] ([[User talk:Example|Talk]])
+        "},
+    ParseError::UnknownSuffix("に変更".to_string()),
+    )]
+    #[case(
+    indoc ! {"\
+            == Bot: [[:Category:Name1]]を[[利用者:Example]]へ ==
+            [[プロジェクト:カテゴリ関連/議論/yyyy年/mm月dd日#XYZ|議論]]を参照。 --[[User:Example|Example]] ([[User talk:Example|Talk]])
+        "},
+    ParseError::NotCategoryLink("利用者:Example".to_string()),
+    )]
+    #[case(
+    indoc ! {"\
+            == Bot: [[:Category:Name1]]から[[:Category:Name2]]へ ==
+            [[プロジェクト:カテゴリ関連/議論/yyyy年/mm月dd日#XYZ|議論]]を参照。 --[[User:Example|Example]] ([[User talk:Example|Talk]])
+        "},
+    ParseError::MissingSeparator("を"),
+    )]
+    #[case(
+    indoc ! {"\
+            == Bot: [[:Category:Name1]]を[[:Category:Name2]]と[[:Category:Name3]]と[[:Category:Name4]]と[[:Category:Name5]]と[[:Category:Name6]]と[[:Category:Name7]]へ ==
+            [[プロジェクト:カテゴリ関連/議論/yyyy年/mm月dd日#XYZ|議論]]を参照。 --[[User:Example|Example]] ([[User talk:Example|Talk]])
+        "},
+    ParseError::TooManyTargets(6),
+    )]
+    #[case(
+    indoc ! {"\
+            == Bot: [[:Category:Name1]]を[[:Category:Name2]]と[[:Category:Name3]]へ移動 ==
+            [[プロジェクト:カテゴリ関連/議論/yyyy年/mm月dd日#XYZ|議論]]を参照。 --[[User:Example|Example]] ([[User talk:Example|Talk]])
+        "},
+    ParseError::MultipleMoveTargets,
+    )]
+    #[case(
+    indoc ! {"\
+            == Bot: [[:Category:Name1]]のソートキーを変更 ==
+            [[プロジェクト:カテゴリ関連/議論/yyyy年/mm月dd日#XYZ|議論]]を参照。 --[[User:Example|Example]] ([[User talk:Example|Talk]])
+        "},
+    ParseError::MissingSortKeyRule,
+    )]
+    #[case(
+    indoc ! {"\
+            == Bot: [[:Category:Name1]]を除去 ==
+            下位カテゴリ: 2階層
+            [[プロジェクト:カテゴリ関連/議論/yyyy年/mm月dd日#XYZ|議論]]を参照。 --[[User:Example|Example]] ([[User talk:Example|Talk]])
+        "},
+    ParseError::SubcategoryNotSupported,
+    )]
+    #[case(
+    indoc ! {"\
+            == Bot: [[:Category:Name1]]を[[:Category:Name2]]へ ==
+            個別ソートキー: Category:Name3 = きい
+            [[プロジェクト:カテゴリ関連/議論/yyyy年/mm月dd日#XYZ|議論]]を参照。 --[[User:Example|Example]] ([[User talk:Example|Talk]])
+        "},
+    ParseError::UnknownSortKeyTarget("Category:Name3".to_string()),
+    )]
+    #[tokio::test]
+    async fn test_parse_failure(
+        #[case] wikitext: &str,
+        #[case] expected: ParseError,
+    ) -> anyhow::Result<()> {
+        let bot = test::bot().await;
+
+        let html = bot
+            .parsoid()
+            .transform_to_html(wikitext)
+            .await?
+            .into_mutable();
+        let sections = html.iter_sections();
+        let section = sections
+            .into_iter()
+            .find(|section| !section.is_pseudo_section())
+            .expect("could not get section");
+
+        let parser = Parser::new(bot.clone(), &section, true)?;
+        let Err(err) = parser.parse() else {
+            panic!("command should not be parsed");
+        };
+
+        assert_eq!(err, expected);
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_parse_excluded_pages() -> anyhow::Result<()> {
+        let bot = test::bot().await;
+
+        let wikitext = indoc! {"\
+            == Bot: [[:Category:Name1]]を[[:Category:Name2]]へ ==
+            除外:
+            * [[記事1]]
+            * [[記事2]]
+            [[プロジェクト:カテゴリ関連/議論/yyyy年/mm月dd日#XYZ|議論]]を参照。 --[[User:Example|Example]] ([[User talk:Example|Talk]])
+        "};
+        let html = bot
+            .parsoid()
+            .transform_to_html(wikitext)
+            .await?
+            .into_mutable();
+        let sections = html.iter_sections();
+        let section = sections
+            .into_iter()
+            .find(|section| !section.is_pseudo_section())
+            .expect("could not get section");
+
+        let parser = Parser::new(bot.clone(), &section, true)?;
+        let command = parser.parse().expect("failed to parse command");
+
+        assert_eq!(command.excluded, ["記事1", "記事2"]);
+        assert_eq!(
+            command.discussion_link,
+            "プロジェクト:カテゴリ関連/議論/yyyy年/mm月dd日#XYZ"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_parse_trial() -> anyhow::Result<()> {
+        let bot = test::bot().await;
+
+        let wikitext = indoc! {"\
+            == Bot (試行): (記事) [[:Category:Name1]]を[[:Category:Name2]]へ ==
+            [[プロジェクト:カテゴリ関連/議論/yyyy年/mm月dd日#XYZ|議論]]を参照。 --[[User:Example|Example]] ([[User talk:Example|Talk]])
+        "};
+        let html = bot
+            .parsoid()
+            .transform_to_html(wikitext)
+            .await?
+            .into_mutable();
+        let sections = html.iter_sections();
+        let section = sections
+            .into_iter()
+            .find(|section| !section.is_pseudo_section())
+            .expect("could not get section");
+
+        let parser = Parser::new(bot.clone(), &section, false)?;
+        let command = parser.parse().expect("failed to parse command");
+
+        assert!(command.trial);
+        assert!(command.dry_run);
+        assert_eq!(command.namespaces, [0]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_subcategory_replacers() -> anyhow::Result<()> {
+        let bot = test::bot().await;
+
+        let wikitext = indoc! {"\
+            == Bot: [[:Category:伊達市]]を[[:Category:北海道伊達市]]へ ==
+            下位カテゴリ: 2階層
+            [[プロジェクト:カテゴリ関連/議論/yyyy年/mm月dd日#XYZ|議論]]を参照。 --[[User:Example|Example]] ([[User talk:Example|Talk]])
+        "};
+        let html = bot
+            .parsoid()
+            .transform_to_html(wikitext)
+            .await?
+            .into_mutable();
+        let sections = html.iter_sections();
+        let section = sections
+            .into_iter()
+            .find(|section| !section.is_pseudo_section())
+            .expect("could not get section");
+
+        let command = Parser::new(bot.clone(), &section, true)?
+            .parse()
+            .expect("failed to parse command");
+        let build = command
+            .subcategory_replacers
+            .expect("subcategory replacers should be built");
+
+        assert!(build("Category:北海道の町").is_none());
+
+        // 2階層目の下位カテゴリ (伊達市 > 伊達市の人物 > 伊達市出身の人物) の所属ページ
+        let (renamed, replacers) =
+            build("Category:伊達市出身の人物").expect("subcategory should match");
+        assert_eq!(renamed, ["Category:北海道伊達市出身の人物"]);
+        let member = bot
+            .parsoid()
+            .transform_to_html("[[Category:伊達市出身の人物]]\n")
+            .await?;
+        let (replaced, changes) = replacers.replace_all(member).await?;
+
+        assert!(!changes.is_empty());
+        assert_eq!(
+            bot.parsoid().transform_to_wikitext(&replaced).await?,
+            "[[Category:北海道伊達市出身の人物]]\n"
+        );
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(
+        "Category:伊達市",
+        "Category:北海道伊達市",
+        "Category:伊達市の人物",
+        Some("Category:北海道伊達市の人物")
+    )]
+    #[case(
+        "Category:伊達市",
+        "Category:伊達市",
+        "Category:伊達市の人物",
+        Some("Category:伊達市の人物")
+    )]
+    #[case(
+        "Category:伊達市",
+        "Category:北海道伊達市",
+        "Category:北海道の町",
+        None
+    )]
+    fn test_rename_subcategory(
+        #[case] from: &str,
+        #[case] to: &str,
+        #[case] subcategory: &str,
+        #[case] expected: Option<&str>,
+    ) {
+        assert_eq!(
+            rename_subcategory(from, to, subcategory).as_deref(),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case("下位カテゴリ: 2階層", Some(2))]
+    #[case("下位カテゴリ：3", Some(3))]
+    #[case("下位カテゴリ: すべて", None)]
+    #[case("議論を参照", None)]
+    fn test_parse_subcategory_depth(#[case] line: &str, #[case] expected: Option<usize>) {
+        assert_eq!(parse_subcategory_depth(line), expected);
+    }
+
+    #[rstest]
+    #[case("Bot: [[:Category:A]]を[[:Category:B]]へ変更", true)]
+    #[case(" Bot: [[:Category:A]]を[[:Category:B]]へ変更", true)]
+    #[case(" Bot (試行): [[:Category:A]]を[[:Category:B]]へ変更", true)]
+    #[case("[[:Category:A]]を[[:Category:B]]へ変更", false)]
+    fn test_is_bot_prefix(#[case] heading: &str, #[case] expected: bool) {
+        assert_eq!(is_bot_prefix(heading), expected);
+    }
+
+    #[rstest]
+    #[case("元のソートキー: 維持", true)]
+    #[case("元のソートキー：維持", true)]
+    #[case("ソートキー: 維持", false)]
+    #[case("ソートキー: DEFAULTSORT", false)]
+    #[case("議論を参照", false)]
+    fn test_is_keep_sort_key(#[case] line: &str, #[case] expected: bool) {
+        assert_eq!(is_keep_sort_key(line), expected);
+    }
+
+    #[rstest]
+    #[case("個別ソートキー: Category:Name2 = きい", Some(("Category:Name2", "きい")))]
+    #[case("個別ソートキー：Name2＝きい", Some(("Name2", "きい")))]
+    #[case("個別ソートキー: Category:Name2 =", None)]
+    #[case("ソートキー: DEFAULTSORT", None)]
+    fn test_parse_target_sort_key(#[case] line: &str, #[case] expected: Option<(&str, &str)>) {
+        assert_eq!(
+            parse_target_sort_key(line),
+            expected.map(|(target, sort_key)| (target.to_string(), sort_key.to_string()))
+        );
+    }
+
+    /// リンク先と異なる表示文字列はソートキーとして扱う
+    #[tokio::test]
+    async fn test_collect_from_to_sort_keys() -> anyhow::Result<()> {
+        let bot = test::bot().await;
+
+        let wikitext = indoc! {"\
+            == [[:Category:Name1]]を[[:Category:Name2|キー]]と[[:Category:Name3]]と[[:Category:Name4|]] ==
+        "};
+        let html = bot
+            .parsoid()
+            .transform_to_html(wikitext)
+            .await?
+            .into_mutable();
+        let sections = html.iter_sections();
+        let section = sections
+            .into_iter()
+            .find(|section| !section.is_pseudo_section())
+            .expect("could not get section");
+        let nodes = section
+            .heading()
+            .expect("could not get heading")
+            .descendants()
+            .skip(1)
+            .collect::<Vec<_>>();
+
+        let (from, to, sort_keys) = collect_from_to(&CategoryNormalizer::new(&bot), &nodes)?;
+
+        assert_eq!(from, "Category:Name1");
+        assert_eq!(to, ["Category:Name2", "Category:Name3", "Category:Name4"]);
+        assert_eq!(
+            sort_keys,
+            HashMap::from([("Category:Name2".to_string(), "キー".to_string())])
+        );
+
+        Ok(())
+    }
+
+    /// リンクの表示文字列、`個別ソートキー:`、`元のソートキー: 維持` を同じコマンドで指定できる.
+    /// 同じ操作先では `個別ソートキー:` を優先する
+    #[tokio::test]
+    async fn test_parse_sort_key_options() -> anyhow::Result<()> {
+        let bot = test::bot().await;
+
+        let wikitext = indoc! {"\
+            == Bot: [[:Category:Name1]]を[[:Category:Name2|けい]]と[[:Category:Name3|かぎ]]へ ==
+            個別ソートキー: Category:Name2 = きい
+            元のソートキー: 維持
+            [[プロジェクト:カテゴリ関連/議論/yyyy年/mm月dd日#XYZ|議論]]を参照。 --[[User:Example|Example]] ([[User talk:Example|Talk]])
+        "};
+        let html = bot
+            .parsoid()
+            .transform_to_html(wikitext)
+            .await?
+            .into_mutable();
+        let section = html
+            .iter_sections()
+            .into_iter()
+            .find(|section| !section.is_pseudo_section())
+            .expect("could not get section");
+
+        let parser = Parser::new(bot.clone(), &section, true)?;
+        let options = parser
+            .replacer_options(
+                &["Category:Name2".to_string(), "Category:Name3".to_string()],
+                HashMap::from([
+                    ("Category:Name2".to_string(), "けい".to_string()),
+                    ("Category:Name3".to_string(), "かぎ".to_string()),
+                ]),
+            )
+            .expect("failed to parse options");
+
+        assert_eq!(
+            options.sort_keys,
+            TargetSortKeys::new(
+                HashMap::from([
+                    ("Category:Name2".to_string(), "きい".to_string()),
+                    ("Category:Name3".to_string(), "かぎ".to_string()),
+                ]),
+                true
+            )
+        );
+        assert!(parser.parse().is_ok());
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case("無効化: image_requested, recursion", &[("image_requested", false), ("recursion", false)])]
+    #[case("有効化：image_requested", &[("image_requested", true)])]
+    #[case("無効化:", &[])]
+    #[case("議論を参照", &[])]
+    fn test_parse_replacer_switches(#[case] line: &str, #[case] expected: &[(&str, bool)]) {
+        let expected = expected
+            .iter()
+            .map(|(name, is_enabled)| (name.to_string(), *is_enabled))
+            .collect::<Vec<_>>();
+        assert_eq!(parse_replacer_switches(line), expected);
+    }
 }
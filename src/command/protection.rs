@@ -0,0 +1,45 @@
+use mwapi_responses::protection::ProtectionInfo;
+
+/// 編集保護の保護レベルに対して、編集に必要な権限
+fn required_right(level: &str) -> &str {
+    match level {
+        "sysop" => "editprotected",
+        "autoconfirmed" => "editsemiprotected",
+        // その他の保護レベルは権限名と同じ
+        level => level,
+    }
+}
+
+/// `rights` を持つ利用者が編集できる保護レベルか
+fn can_edit_with<'a>(levels: impl IntoIterator<Item = &'a str>, rights: &[String]) -> bool {
+    levels
+        .into_iter()
+        .all(|level| rights.iter().any(|right| right == required_right(level)))
+}
+
+/// `rights` を持つ利用者がページを編集できるか
+pub fn can_edit(protection: &[ProtectionInfo], rights: &[String]) -> bool {
+    let levels = protection
+        .iter()
+        .filter(|protection| protection.type_ == "edit")
+        .map(|protection| protection.level.as_str());
+    can_edit_with(levels, rights)
+}
+
+#[cfg(test)]
+mod test {
+    use rstest::rstest;
+
+    use crate::command::protection::can_edit_with;
+
+    #[rstest]
+    #[case(&[], &["edit"], true)]
+    #[case(&["autoconfirmed"], &["edit", "editsemiprotected"], true)]
+    #[case(&["sysop"], &["edit", "editsemiprotected"], false)]
+    #[case(&["extendedconfirmed"], &["edit", "extendedconfirmed"], true)]
+    #[case(&["autoconfirmed", "sysop"], &["editsemiprotected"], false)]
+    fn test_can_edit(#[case] levels: &[&str], #[case] rights: &[&str], #[case] expected: bool) {
+        let rights = rights.iter().map(ToString::to_string).collect::<Vec<_>>();
+        assert_eq!(can_edit_with(levels.iter().copied(), &rights), expected);
+    }
+}
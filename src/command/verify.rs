@@ -0,0 +1,209 @@
+use indexmap::IndexMap;
+use mwbot::generators::{CategoryMembers, Generator};
+use mwbot::Bot;
+
+use crate::action::{get_page_info, is_category_member, purge_page};
+use crate::command::{OperationResult, OperationStatus};
+use crate::generator::dump::category_tag_targets;
+use crate::title::CategoryNormalizer;
+
+/// 実行後も移動元のカテゴリに残っているページについて、考えられる理由
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RemainingReason {
+    /// 置換するカテゴリタグがなかったため、テンプレートやモジュールによって付与されている
+    Template,
+    /// 編集後も本文にカテゴリタグが残っている
+    TagRemains,
+    /// 保護されているため編集できなかった
+    Protected,
+    /// 編集に失敗した
+    EditFailed,
+    /// {{nobots}}などによりBotの編集が拒否されている
+    BotsDenied,
+    /// {{使用中}}などが貼られていたため編集しなかった
+    InUse,
+    /// 実行開始時にはカテゴリに所属していなかった
+    AddedSinceStart,
+}
+
+impl RemainingReason {
+    /// 実行時の結果から理由を推定する. 残っていて問題ないページは `None` を返す
+    pub fn classify(result: Option<&OperationResult>, is_protected: bool) -> Option<Self> {
+        match result {
+            None => Some(Self::AddedSinceStart),
+            Some(Ok(OperationStatus::Protected)) => Some(Self::Protected),
+            Some(Ok(OperationStatus::BotsDenied)) => Some(Self::BotsDenied),
+            Some(Ok(OperationStatus::Deferred | OperationStatus::InUse)) => Some(Self::InUse),
+            Some(Err(_) | Ok(OperationStatus::EditConflicted)) if is_protected => {
+                Some(Self::Protected)
+            }
+            Some(Err(_) | Ok(OperationStatus::EditConflicted)) => Some(Self::EditFailed),
+            Some(Ok(
+                OperationStatus::Done
+                | OperationStatus::Skipped
+                | OperationStatus::CategoryFromTemplate(_),
+            )) => Some(Self::Template),
+            Some(Ok(OperationStatus::Excluded | OperationStatus::Previewed { .. })) => None,
+        }
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            Self::Template => "テンプレートなどによりカテゴリが付与されています",
+            Self::TagRemains => "本文にカテゴリタグが残っています",
+            Self::Protected => "保護されているため編集できませんでした",
+            Self::EditFailed => "編集に失敗しました",
+            Self::BotsDenied => "Botによる編集が拒否されています",
+            Self::InUse => "使用中のため編集しませんでした",
+            Self::AddedSinceStart => "実行開始後にカテゴリに追加されました",
+        }
+    }
+}
+
+/// `category` を再度取得し、残っているページとその理由を返す.
+/// 編集直後はカテゴリの所属が更新されていないことがあるため、テンプレートなどによる付与と推定したページは確かめ直す
+pub async fn find_remaining_members(
+    bot: &Bot,
+    category: &str,
+    namespaces: Vec<u32>,
+    statuses: &IndexMap<String, OperationResult>,
+) -> anyhow::Result<IndexMap<String, RemainingReason>> {
+    let mut members = CategoryMembers::new(category.to_string())
+        .namespace(namespaces)
+        .generate(bot);
+
+    let normalizer = CategoryNormalizer::new(bot);
+    let mut remaining = IndexMap::new();
+    while let Some(page) = members.recv().await {
+        let title = page?.title().to_string();
+        let result = statuses.get(&title);
+
+        let is_protected = match result {
+            Some(Err(_) | Ok(OperationStatus::EditConflicted)) => {
+                is_edit_protected(bot, &title).await?
+            }
+            _ => false,
+        };
+        let reason = match RemainingReason::classify(result, is_protected) {
+            Some(RemainingReason::Template) => {
+                confirm_template(bot, &normalizer, &title, category).await?
+            }
+            reason => reason,
+        };
+        if let Some(reason) = reason {
+            remaining.insert(title, reason);
+        }
+    }
+
+    Ok(remaining)
+}
+
+/// 本文にカテゴリタグが残っていなければ、パージしてもなお所属している場合のみテンプレートなどによる付与とみなす
+async fn confirm_template(
+    bot: &Bot,
+    normalizer: &CategoryNormalizer,
+    title: &str,
+    category: &str,
+) -> anyhow::Result<Option<RemainingReason>> {
+    let wikitext = bot.page(title)?.wikitext().await?;
+    if has_category_tag(normalizer, &wikitext, category) {
+        return Ok(Some(RemainingReason::TagRemains));
+    }
+
+    purge_page(bot, title).await?;
+    Ok(is_category_member(bot, title, category)
+        .await?
+        .then_some(RemainingReason::Template))
+}
+
+/// `wikitext` に `category` のカテゴリタグがあるか
+fn has_category_tag(normalizer: &CategoryNormalizer, wikitext: &str, category: &str) -> bool {
+    category_tag_targets(wikitext).any(|target| {
+        normalizer
+            .normalize(target)
+            .is_some_and(|target| normalizer.is_same(&target, category))
+    })
+}
+
+/// 管理者のみ編集できるように保護されているか
+async fn is_edit_protected(bot: &Bot, title: &str) -> anyhow::Result<bool> {
+    let info = get_page_info(bot, title).await?;
+    Ok(info
+        .protection
+        .iter()
+        .any(|protection| protection.type_ == "edit" && protection.level == "sysop"))
+}
+
+#[cfg(test)]
+mod test {
+    use rstest::rstest;
+
+    use crate::command::verify::{has_category_tag, RemainingReason};
+    use crate::command::{OperationResult, OperationStatus};
+    use crate::title::CategoryNormalizer;
+    use crate::util::test;
+
+    #[rstest]
+    #[case(None, false, Some(RemainingReason::AddedSinceStart))]
+    #[case(
+        Some(Ok(OperationStatus::Done)),
+        false,
+        Some(RemainingReason::Template)
+    )]
+    #[case(
+        Some(Ok(OperationStatus::Skipped)),
+        false,
+        Some(RemainingReason::Template)
+    )]
+    #[case(
+        Some(Ok(OperationStatus::CategoryFromTemplate(vec!["Template:Example".to_string()]))),
+        false,
+        Some(RemainingReason::Template)
+    )]
+    #[case(
+        Some(Ok(OperationStatus::Protected)),
+        false,
+        Some(RemainingReason::Protected)
+    )]
+    #[case(
+        Some(Ok(OperationStatus::BotsDenied)),
+        false,
+        Some(RemainingReason::BotsDenied)
+    )]
+    #[case(Some(Ok(OperationStatus::InUse)), false, Some(RemainingReason::InUse))]
+    #[case(Some(Ok(OperationStatus::Excluded)), false, None)]
+    #[case(Some(Err("ページの保存に失敗しました".to_string())), false, Some(RemainingReason::EditFailed))]
+    #[case(Some(Err("ページの保存に失敗しました".to_string())), true, Some(RemainingReason::Protected))]
+    #[case(
+        Some(Ok(OperationStatus::EditConflicted)),
+        false,
+        Some(RemainingReason::EditFailed)
+    )]
+    fn test_classify(
+        #[case] result: Option<OperationResult>,
+        #[case] is_protected: bool,
+        #[case] expected: Option<RemainingReason>,
+    ) {
+        assert_eq!(
+            RemainingReason::classify(result.as_ref(), is_protected),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case("本文\n[[Category:Name1|あ]]", true)]
+    #[case("[[カテゴリ: Name1]]", true)]
+    #[case("[[:Category:Name1]]へのリンク", false)]
+    #[case("[[Name1]]", false)]
+    #[case("{{Example}}", false)]
+    #[tokio::test]
+    async fn test_has_category_tag(#[case] wikitext: &str, #[case] expected: bool) {
+        let bot = test::bot().await;
+        let normalizer = CategoryNormalizer::new(&bot);
+
+        assert_eq!(
+            has_category_tag(&normalizer, wikitext, "Category:Name1"),
+            expected
+        );
+    }
+}
@@ -1,10 +1,15 @@
 use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
 
 use anyhow::Context;
 use config::Config;
 use indexmap::IndexMap;
 use serde::Deserialize;
 
+/// Wiki上で管理している設定
+const ON_WIKI_CONFIG_URL: &str =
+    "https://ja.wikipedia.org/wiki/利用者:QueueBot/config.json?action=raw";
+
 pub fn load_config() -> anyhow::Result<QueueBotConfig> {
     from_path("queuebot")
 }
@@ -21,6 +26,10 @@ pub fn from_path(path: impl AsRef<str>) -> anyhow::Result<QueueBotConfig> {
 #[derive(Deserialize, Debug)]
 pub struct QueueBotConfig {
     pub mysql: MySqlConfig,
+    #[serde(default)]
+    pub command: CommandConfig,
+    #[serde(default)]
+    pub daemon: DaemonConfig,
 }
 
 #[derive(Deserialize, Debug)]
@@ -28,9 +37,173 @@ pub struct MySqlConfig {
     pub connection_url: String,
 }
 
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct CommandConfig {
+    /// ページの取得と置換を同時に行う数
+    pub concurrency: usize,
+    /// ページを保存する最小間隔(秒).
+    /// 0の場合はmwbotの `save_delay` のみに従う
+    pub save_interval_secs: u64,
+    /// 使用中のページを後回しにし続ける期間(秒).
+    /// 過ぎた場合は編集を諦める
+    pub in_use_timeout_secs: u64,
+    /// コマンドで指定できる下位カテゴリの深さの上限
+    pub max_subcategory_depth: usize,
+    /// 所属ページを取得するダンプ. 指定しない場合はAPIのみを使う
+    pub dump: Option<DumpConfig>,
+    /// 使う置換処理. コマンドの `有効化:` や `無効化:` で変えられる
+    pub replacers: ReplacerConfig,
+}
+
+impl Default for CommandConfig {
+    fn default() -> Self {
+        Self {
+            concurrency: 4,
+            save_interval_secs: 0,
+            in_use_timeout_secs: 259_200,
+            max_subcategory_depth: 3,
+            dump: None,
+            replacers: ReplacerConfig::default(),
+        }
+    }
+}
+
+/// 標準で使う置換処理
+const DEFAULT_REPLACERS: [&str; 6] = [
+    "recursion",
+    "category_tag",
+    "category_of_redirects",
+    "image_requested",
+    "template_parameter",
+    "sort_key",
+];
+
+/// 使う置換処理の名前. 記載した順に実行する.
+/// `recursion` を含む場合は、他の置換処理をテンプレートの引数の中にも適用する
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ReplacerConfig {
+    pub enabled: Vec<String>,
+    /// 名前空間ごとの指定. 指定のない名前空間は `enabled` に従う
+    pub namespaces: Vec<NamespaceReplacers>,
+}
+
+impl Default for ReplacerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: DEFAULT_REPLACERS.map(ToString::to_string).to_vec(),
+            namespaces: Vec::new(),
+        }
+    }
+}
+
+impl ReplacerConfig {
+    /// 全ての名前空間で、`switches` の置換処理を有効(`true`)・無効(`false`)にする.
+    /// 有効にした置換処理は、使っていなければ最後に実行する
+    pub fn switched(&self, switches: &[(String, bool)]) -> Self {
+        let mut config = self.clone();
+        let lists = std::iter::once(&mut config.enabled).chain(
+            config
+                .namespaces
+                .iter_mut()
+                .map(|namespace| &mut namespace.enabled),
+        );
+        for enabled in lists {
+            for (name, is_enabled) in switches {
+                if !is_enabled {
+                    enabled.retain(|enabled| enabled != name);
+                } else if !enabled.contains(name) {
+                    enabled.push(name.clone());
+                }
+            }
+        }
+        config
+    }
+}
+
+/// `namespaces` の名前空間のページに使う置換処理
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct NamespaceReplacers {
+    pub namespaces: Vec<u32>,
+    pub enabled: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DumpConfig {
+    #[serde(flatten)]
+    pub files: DumpFiles,
+    /// APIの代わりにダンプから所属ページを取得する.
+    /// `false` の場合はAPIから取得し、ダンプの時点から所属ページが変わったかを報告する
+    #[serde(default)]
+    pub list_members: bool,
+}
+
+/// ダンプの形式とファイル
+#[derive(Deserialize, Debug)]
+#[serde(tag = "format", rename_all = "snake_case")]
+pub enum DumpFiles {
+    /// categorylinksとpageのSQLダンプ
+    Sql {
+        categorylinks: PathBuf,
+        page: PathBuf,
+    },
+    /// pages-articlesのXMLダンプ
+    Xml { pages_articles: PathBuf },
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct DaemonConfig {
+    /// キューを再読込する間隔(秒)
+    pub poll_interval_secs: u64,
+    /// キューのページの版が更新されたか確認する間隔(秒)
+    pub revision_check_interval_secs: u64,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval_secs: 600,
+            revision_check_interval_secs: 30,
+        }
+    }
+}
+
+pub async fn load_on_wiki_config() -> anyhow::Result<OnWikiConfig> {
+    let config = reqwest::get(ON_WIKI_CONFIG_URL)
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    Ok(config)
+}
+
 #[derive(Deserialize, Debug)]
 pub struct OnWikiConfig {
     pub discussion_summary_icon_bindings: Vec<DiscussionSummaryIconBindings>,
+    #[serde(default)]
+    pub template_parameter_rules: Vec<TemplateParameterRule>,
+}
+
+/// 引数にカテゴリ名の一部を持つテンプレートの置換規則.
+/// 例えば `{{画像提供依頼|cat=北海道}}` は `Category:北海道の画像提供依頼` を付与する
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct TemplateParameterRule {
+    /// 対象のテンプレート名 (`Template:` を含む)
+    pub templates: Vec<String>,
+    /// カテゴリ名の一部を持つ引数名
+    pub param: String,
+    /// `cat`, `cat2`, `cat3` のように番号付きの引数で複数のカテゴリを指定できるか
+    #[serde(default)]
+    pub numbered: bool,
+    /// 引数の値の前に付くカテゴリ名の部分
+    #[serde(default)]
+    pub prefix: String,
+    /// 引数の値の後に付くカテゴリ名の部分
+    #[serde(default)]
+    pub suffix: String,
 }
 
 #[derive(Deserialize, Debug)]
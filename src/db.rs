@@ -1,13 +1,18 @@
+use std::time::Duration;
+
 use anyhow::Context as _;
 use backon::{ExponentialBuilder, Retryable as _};
-use sqlx::{query, MySqlPool, QueryBuilder};
+use sqlx::{query, Connection as _, MySqlConnection, MySqlPool, QueryBuilder};
 use tap::Tap;
 use tokio::sync::OnceCell;
+use tracing::warn;
 use ulid::Ulid;
 use uuid::Uuid;
 
-use crate::command::Command;
+use crate::command::fingerprint::SectionFingerprint;
+use crate::command::{Command, OperationResult, OperationStatus};
 use crate::config::MySqlConfig;
+use crate::replacer::ChangeRecord;
 
 static POOL: OnceCell<MySqlPool> = OnceCell::const_new();
 
@@ -20,6 +25,47 @@ fn pool() -> &'static MySqlPool {
     POOL.get().expect("Database Pool is not initialized")
 }
 
+const INSTANCE_LOCK_NAME: &str = "queuebot.queue_consume";
+
+/// 複数のプロセスが同時にコマンドを実行しないためのロック.
+/// ロックは専用の接続に紐付いているため、ドロップして接続が切れると解放される
+pub struct InstanceLock {
+    conn: MySqlConnection,
+}
+
+impl InstanceLock {
+    /// ロックを保持し続けているか. 接続が切れていた場合は `false` を返す
+    pub async fn is_held(&mut self) -> bool {
+        let held: Result<Option<i64>, _> =
+            sqlx::query_scalar("SELECT IS_USED_LOCK(?) = CONNECTION_ID()")
+                .bind(INSTANCE_LOCK_NAME)
+                .fetch_one(&mut self.conn)
+                .await;
+        matches!(held, Ok(Some(1)))
+    }
+
+    pub async fn release(mut self) -> anyhow::Result<()> {
+        sqlx::query("SELECT RELEASE_LOCK(?)")
+            .bind(INSTANCE_LOCK_NAME)
+            .execute(&mut self.conn)
+            .await?;
+        self.conn.close().await?;
+        Ok(())
+    }
+}
+
+/// ロックを取得する. 他のプロセスが保持している場合は待たずに `None` を返す
+pub async fn try_lock_instance() -> anyhow::Result<Option<InstanceLock>> {
+    // プールに戻すと別の処理がロックを持った接続を使ってしまうため、プールから切り離す
+    let mut conn = pool().acquire().await?.detach();
+    let acquired: Option<i64> = sqlx::query_scalar("SELECT GET_LOCK(?, 0)")
+        .bind(INSTANCE_LOCK_NAME)
+        .fetch_one(&mut conn)
+        .await?;
+
+    Ok((acquired == Some(1)).then_some(InstanceLock { conn }))
+}
+
 pub async fn store_command<R>(command: &Command<R>) -> anyhow::Result<()> {
     let command_id: Uuid = command.id.into();
     let command_id = command_id.as_bytes().as_slice();
@@ -29,10 +75,12 @@ pub async fn store_command<R>(command: &Command<R>) -> anyhow::Result<()> {
         let mut tx = pool.begin().await.context("could not begin transaction")?;
 
         query!(
-            "INSERT INTO commands (id, command_type, discussion_link) VALUES (?, ?, ?)",
+            "INSERT INTO commands (id, command_type, discussion_link, heading, requester) VALUES (?, ?, ?, ?, ?)",
             &command_id,
             &command.command_type,
-            &command.discussion_link
+            &command.discussion_link,
+            &command.fingerprint.heading,
+            &command.fingerprint.requester
         )
         .execute(&mut *tx)
         .await?;
@@ -65,6 +113,20 @@ pub async fn store_command<R>(command: &Command<R>) -> anyhow::Result<()> {
             insert_to_categories_query.build().execute(&mut *tx).await?;
         }
 
+        if !command.excluded.is_empty() {
+            let mut insert_excluded_pages_query =
+                QueryBuilder::new("INSERT INTO command_excluded_pages (command_id, title) ")
+                    .tap_mut(|builder| {
+                        builder.push_values(&command.excluded, |mut b, title| {
+                            b.push_bind(command_id).push_bind(title);
+                        });
+                    });
+            insert_excluded_pages_query
+                .build()
+                .execute(&mut *tx)
+                .await?;
+        }
+
         tx.commit().await?;
 
         Ok(())
@@ -84,23 +146,29 @@ pub enum CommandType {
     Reassignment,
     Remove,
     Duplicate,
+    Move,
+    SortKey,
 }
 
+/// 保存した版を、置換処理ごとの変更内容とともに記録する
 pub async fn store_operation(
     command_id: &Ulid,
     page_id: u32,
     new_revid: u64,
+    changes: &[ChangeRecord],
 ) -> anyhow::Result<()> {
     let id: Uuid = Ulid::new().into();
     let command_id: Uuid = (*command_id).into();
+    let changes = serde_json::to_string(changes)?;
     let save = || async {
         let pool = pool();
         sqlx::query!(
-            "INSERT INTO operations VALUES (?, ?, ?, ?)",
+            "INSERT INTO operations VALUES (?, ?, ?, ?, ?)",
             id.as_bytes().as_slice(),
             command_id.as_bytes().as_slice(),
             page_id,
-            new_revid
+            new_revid,
+            changes
         )
         .execute(pool)
         .await?;
@@ -115,3 +183,319 @@ pub async fn store_operation(
     )
     .await
 }
+
+pub async fn store_move_operation(
+    command_id: &Ulid,
+    page_id: u32,
+    from_title: &str,
+    to_title: &str,
+) -> anyhow::Result<()> {
+    let id: Uuid = Ulid::new().into();
+    let command_id: Uuid = (*command_id).into();
+    let save = || async {
+        let pool = pool();
+        sqlx::query!(
+            "INSERT INTO move_operations VALUES (?, ?, ?, ?, ?)",
+            id.as_bytes().as_slice(),
+            command_id.as_bytes().as_slice(),
+            page_id,
+            from_title,
+            to_title
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    };
+
+    save.retry(
+        &ExponentialBuilder::default()
+            .with_jitter()
+            .with_max_times(5),
+    )
+    .await
+}
+
+/// 見出しと依頼者が `fingerprint` と同じコマンドのうち、完了していない最新のもののIDを返す
+pub async fn find_resumable_command(
+    fingerprint: &SectionFingerprint,
+) -> anyhow::Result<Option<Ulid>> {
+    let find = || async {
+        let id: Option<Vec<u8>> = sqlx::query_scalar(
+            "SELECT id FROM commands WHERE heading = ? AND requester = ? AND finished = FALSE \
+             ORDER BY id DESC LIMIT 1",
+        )
+        .bind(&fingerprint.heading)
+        .bind(&fingerprint.requester)
+        .fetch_optional(pool())
+        .await?;
+
+        id.map(|id| Ok(Uuid::from_slice(&id)?.into())).transpose()
+    };
+
+    find.retry(
+        &ExponentialBuilder::default()
+            .with_jitter()
+            .with_max_times(5),
+    )
+    .await
+}
+
+pub async fn finish_command(command_id: &Ulid) -> anyhow::Result<()> {
+    let command_id: Uuid = (*command_id).into();
+    let save = || async {
+        sqlx::query!(
+            "UPDATE commands SET finished = TRUE WHERE id = ?",
+            command_id.as_bytes().as_slice()
+        )
+        .execute(pool())
+        .await?;
+
+        Ok(())
+    };
+
+    save.retry(
+        &ExponentialBuilder::default()
+            .with_jitter()
+            .with_max_times(5),
+    )
+    .await
+}
+
+/// カテゴリページの移動を既に行ったか
+pub async fn is_category_moved(command_id: &Ulid) -> anyhow::Result<bool> {
+    let command_id: Uuid = (*command_id).into();
+    let find = || async {
+        let count: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM move_operations WHERE command_id = ?")
+                .bind(command_id.as_bytes().as_slice())
+                .fetch_one(pool())
+                .await?;
+
+        Ok(count > 0)
+    };
+
+    find.retry(
+        &ExponentialBuilder::default()
+            .with_jitter()
+            .with_max_times(5),
+    )
+    .await
+}
+
+#[derive(sqlx::Type, Debug, PartialEq)]
+#[sqlx(rename_all = "lowercase")]
+enum ProgressStatus {
+    Done,
+    Skipped,
+    Excluded,
+    Conflicted,
+    Template,
+    Protected,
+    Denied,
+    Deferred,
+    InUse,
+    Error,
+}
+
+/// ページごとの処理結果を記録する. 試行モードの結果は記録しない
+pub async fn store_progress(
+    command_id: &Ulid,
+    title: &str,
+    result: &OperationResult,
+) -> anyhow::Result<()> {
+    let (status, message) = match result {
+        Ok(OperationStatus::Done) => (ProgressStatus::Done, None),
+        Ok(OperationStatus::Skipped) => (ProgressStatus::Skipped, None),
+        Ok(OperationStatus::Excluded) => (ProgressStatus::Excluded, None),
+        Ok(OperationStatus::EditConflicted) => (ProgressStatus::Conflicted, None),
+        Ok(OperationStatus::CategoryFromTemplate(templates)) => {
+            (ProgressStatus::Template, Some(templates.join("\n")))
+        }
+        Ok(OperationStatus::Previewed { .. }) => return Ok(()),
+        Ok(OperationStatus::Protected) => (ProgressStatus::Protected, None),
+        Ok(OperationStatus::BotsDenied) => (ProgressStatus::Denied, None),
+        Ok(OperationStatus::Deferred) => (ProgressStatus::Deferred, None),
+        Ok(OperationStatus::InUse) => (ProgressStatus::InUse, None),
+        Err(message) => (ProgressStatus::Error, Some(message.clone())),
+    };
+
+    let command_id: Uuid = (*command_id).into();
+    let save = || async {
+        sqlx::query!(
+            "INSERT INTO command_progress (command_id, title, status, message) VALUES (?, ?, ?, ?) \
+             ON DUPLICATE KEY UPDATE status = VALUES(status), message = VALUES(message), \
+             processed_at = CURRENT_TIMESTAMP(6)",
+            command_id.as_bytes().as_slice(),
+            title,
+            &status,
+            message
+        )
+        .execute(pool())
+        .await?;
+
+        Ok(())
+    };
+
+    save.retry(
+        &ExponentialBuilder::default()
+            .with_jitter()
+            .with_max_times(5),
+    )
+    .await
+}
+
+#[derive(sqlx::FromRow)]
+struct Progress {
+    title: String,
+    status: ProgressStatus,
+    message: Option<String>,
+}
+
+/// 記録済みのページごとの処理結果を処理順に返す
+pub async fn load_progress(command_id: &Ulid) -> anyhow::Result<Vec<(String, OperationResult)>> {
+    let command_id: Uuid = (*command_id).into();
+    let load = || async {
+        let progress: Vec<Progress> = sqlx::query_as(
+            "SELECT title, status, message FROM command_progress WHERE command_id = ? ORDER BY processed_at",
+        )
+        .bind(command_id.as_bytes().as_slice())
+        .fetch_all(pool())
+        .await?;
+
+        Ok(progress
+            .into_iter()
+            .map(|progress| {
+                let result = match progress.status {
+                    ProgressStatus::Done => Ok(OperationStatus::Done),
+                    ProgressStatus::Skipped => Ok(OperationStatus::Skipped),
+                    ProgressStatus::Excluded => Ok(OperationStatus::Excluded),
+                    ProgressStatus::Conflicted => Ok(OperationStatus::EditConflicted),
+                    ProgressStatus::Template => Ok(OperationStatus::CategoryFromTemplate(
+                        progress
+                            .message
+                            .unwrap_or_default()
+                            .lines()
+                            .map(ToString::to_string)
+                            .collect(),
+                    )),
+                    ProgressStatus::Protected => Ok(OperationStatus::Protected),
+                    ProgressStatus::Denied => Ok(OperationStatus::BotsDenied),
+                    ProgressStatus::Deferred => Ok(OperationStatus::Deferred),
+                    ProgressStatus::InUse => Ok(OperationStatus::InUse),
+                    ProgressStatus::Error => Err(progress.message.unwrap_or_else(|| {
+                        warn!(title = progress.title, "error message is missing");
+                        String::new()
+                    })),
+                };
+                (progress.title, result)
+            })
+            .collect())
+    };
+
+    load.retry(
+        &ExponentialBuilder::default()
+            .with_jitter()
+            .with_max_times(5),
+    )
+    .await
+}
+
+/// 使用中のため後回しにしたページを記録する. 既に記録されている場合は最初に後回しにした日時を保つ
+pub async fn store_deferred_page(command_id: &Ulid, title: &str) -> anyhow::Result<()> {
+    let command_id: Uuid = (*command_id).into();
+    let save = || async {
+        sqlx::query!(
+            "INSERT IGNORE INTO deferred_pages (command_id, title) VALUES (?, ?)",
+            command_id.as_bytes().as_slice(),
+            title
+        )
+        .execute(pool())
+        .await?;
+
+        Ok(())
+    };
+
+    save.retry(
+        &ExponentialBuilder::default()
+            .with_jitter()
+            .with_max_times(5),
+    )
+    .await
+}
+
+/// 後回しにしたページと、最初に後回しにしてからの経過時間を返す
+pub async fn load_deferred_pages(command_id: &Ulid) -> anyhow::Result<Vec<(String, Duration)>> {
+    let command_id: Uuid = (*command_id).into();
+    let load = || async {
+        let deferred: Vec<(String, i64)> = sqlx::query_as(
+            "SELECT title, TIMESTAMPDIFF(SECOND, deferred_at, CURRENT_TIMESTAMP) FROM deferred_pages WHERE command_id = ?",
+        )
+        .bind(command_id.as_bytes().as_slice())
+        .fetch_all(pool())
+        .await?;
+
+        Ok(deferred
+            .into_iter()
+            .map(|(title, elapsed)| (title, Duration::from_secs(elapsed.max(0) as u64)))
+            .collect())
+    };
+
+    load.retry(
+        &ExponentialBuilder::default()
+            .with_jitter()
+            .with_max_times(5),
+    )
+    .await
+}
+
+/// 処理済みのキューの結果(`完了` や `不受理` など)を記録する
+pub async fn store_section_outcome(
+    fingerprint: &SectionFingerprint,
+    outcome: &str,
+) -> anyhow::Result<()> {
+    let save = || async {
+        sqlx::query!(
+            "INSERT INTO handled_sections (heading, requester, outcome) VALUES (?, ?, ?) \
+             ON DUPLICATE KEY UPDATE outcome = VALUES(outcome), handled_at = CURRENT_TIMESTAMP",
+            &fingerprint.heading,
+            &fingerprint.requester,
+            outcome
+        )
+        .execute(pool())
+        .await?;
+
+        Ok(())
+    };
+
+    save.retry(
+        &ExponentialBuilder::default()
+            .with_jitter()
+            .with_max_times(5),
+    )
+    .await
+}
+
+/// 処理済みのキューであればその結果を返す
+pub async fn find_section_outcome(
+    fingerprint: &SectionFingerprint,
+) -> anyhow::Result<Option<String>> {
+    let find = || async {
+        let outcome = sqlx::query_scalar(
+            "SELECT outcome FROM handled_sections WHERE heading = ? AND requester = ?",
+        )
+        .bind(&fingerprint.heading)
+        .bind(&fingerprint.requester)
+        .fetch_optional(pool())
+        .await?;
+
+        Ok(outcome)
+    };
+
+    find.retry(
+        &ExponentialBuilder::default()
+            .with_jitter()
+            .with_max_times(5),
+    )
+    .await
+}
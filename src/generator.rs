@@ -1,59 +1,657 @@
-use std::collections::HashSet;
+use std::collections::{HashSet, VecDeque};
+use std::fmt::Debug;
+use std::future::Future;
 use std::sync::{Arc, Mutex};
 
-use mwbot::generators::{CategoryMembers, Generator, Search};
+use backon::{BackoffBuilder as _, ExponentialBuilder};
+use mwbot::generators::file::FileUsage;
+use mwbot::generators::link::LinksHere;
+use mwbot::generators::{CategoryMembers, EmbeddedIn, Generator, Search};
 use mwbot::{Bot, Page, Result};
 use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::time;
+use tracing::warn;
 
-/// カテゴリに所属する全ページを返す.
+use crate::db::CommandType;
+use crate::title::{category_namespace_names, CategoryNormalizer};
+
+pub mod dump;
+
+/// 操作対象となるページの取得元
+pub trait MemberSource: Debug + Send + Sync {
+    /// `category` の操作対象となるページを返す
+    fn generate(&self, bot: &Bot, category: &str, namespaces: &[u32]) -> Receiver<Result<Page>>;
+
+    /// 返すページがカテゴリに所属しているページのみか. 検索結果などを返す場合は `false`
+    fn lists_members_only(&self) -> bool {
+        false
+    }
+}
+
+/// カテゴリに所属するページ
+#[derive(Debug)]
+pub struct CategoryMemberSource;
+
+impl MemberSource for CategoryMemberSource {
+    fn generate(&self, bot: &Bot, category: &str, namespaces: &[u32]) -> Receiver<Result<Page>> {
+        CategoryMembers::new(category.to_string())
+            .namespace(namespaces.to_vec())
+            .generate(bot)
+    }
+
+    fn lists_members_only(&self) -> bool {
+        true
+    }
+}
+
+/// 取得したページのタイトルを `recorded` に記録する取得元.
+/// 取得元の重複を除く前のページを記録するため、取得元ごとの結果を比べられる
+#[derive(Debug)]
+pub struct RecordingSource {
+    inner: Box<dyn MemberSource>,
+    recorded: Arc<Mutex<HashSet<String>>>,
+}
+
+impl RecordingSource {
+    pub fn new(inner: Box<dyn MemberSource>, recorded: Arc<Mutex<HashSet<String>>>) -> Self {
+        Self { inner, recorded }
+    }
+}
+
+impl MemberSource for RecordingSource {
+    fn generate(&self, bot: &Bot, category: &str, namespaces: &[u32]) -> Receiver<Result<Page>> {
+        let mut inner = self.inner.generate(bot, category, namespaces);
+        let (tx, rx) = mpsc::channel(50);
+        let recorded = self.recorded.clone();
+        tokio::spawn(async move {
+            while let Some(page) = inner.recv().await {
+                if let Ok(page) = &page {
+                    recorded.lock().unwrap().insert(page.title().to_string());
+                }
+                if tx.send(page).await.is_err() {
+                    // Receiver hung up, just abort
+                    return;
+                }
+            }
+        });
+        rx
+    }
+
+    fn lists_members_only(&self) -> bool {
+        self.inner.lists_members_only()
+    }
+}
+
+/// ソースにカテゴリタグが含まれるページ.
+/// カテゴリ名で絞り込んだ上で、siteinfoから取得した `カテゴリ:` などの別名を含めた正規表現で名前空間の接頭辞を確かめる
+#[derive(Debug)]
+pub struct InsourceSource;
+
+impl MemberSource for InsourceSource {
+    fn generate(&self, bot: &Bot, category: &str, namespaces: &[u32]) -> Receiver<Result<Page>> {
+        let (tx, rx) = mpsc::channel(50);
+        let bot = bot.clone();
+        let category = category.to_string();
+        let namespaces = namespaces.to_vec();
+        tokio::spawn(async move {
+            let names = match category_namespace_names(&bot).await {
+                Ok(names) => names,
+                Err(err) => {
+                    let _ = tx.send(Err(err)).await;
+                    return;
+                }
+            };
+            let mut pages = Search::new(insource_query(&category, &names))
+                .namespace(namespaces)
+                .generate(&bot);
+            while let Some(page) = pages.recv().await {
+                if tx.send(page).await.is_err() {
+                    // Receiver hung up, just abort
+                    return;
+                }
+            }
+        });
+        rx
+    }
+}
+
+/// `category` のカテゴリタグを探す検索語. `namespace_names` はカテゴリ名前空間の名前と別名
+fn insource_query(category: &str, namespace_names: &[String]) -> String {
+    let name = category.trim_start_matches("Category:");
+    let namespace_pattern = namespace_names
+        .iter()
+        .map(|name| regex_pattern(name))
+        .collect::<Vec<_>>()
+        .join("|");
+    format!(
+        r#"insource:"{name}" insource:/\[\[ *({namespace_pattern}) *: *{} *(\]|\|)/i"#,
+        regex_pattern(name)
+    )
+}
+
+/// 検索の正規表現で `text` に一致するパターン. 空白と `_` は区別しない
+fn regex_pattern(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            ' ' | '_' => "[ _]".to_string(),
+            c if r#".?+*|{}[]()"\#@&<>~/"#.contains(c) => format!("\\{c}"),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+/// カテゴリに所属するテンプレートを参照読み込みしているページ.
+/// テンプレートがカテゴリを付与している場合、参照読み込みしているページもカテゴリに所属する
+#[derive(Debug)]
+pub struct EmbeddedInSource {
+    templates: Box<dyn MemberSource>,
+}
+
+impl EmbeddedInSource {
+    /// `templates` が返すテンプレートを参照読み込みしているページを返す
+    pub fn new(templates: Box<dyn MemberSource>) -> Self {
+        Self { templates }
+    }
+}
+
+impl Default for EmbeddedInSource {
+    fn default() -> Self {
+        Self::new(Box::new(CategoryMemberSource))
+    }
+}
+
+impl MemberSource for EmbeddedInSource {
+    fn generate(&self, bot: &Bot, category: &str, namespaces: &[u32]) -> Receiver<Result<Page>> {
+        let templates = self.templates.generate(bot, category, &[NS_TEMPLATE]);
+        let bot = bot.clone();
+        let namespaces = namespaces.to_vec();
+        expand_pages(templates, move |template| {
+            EmbeddedIn::new(template.title().to_string())
+                .namespace(namespaces.clone())
+                .generate(&bot)
+        })
+    }
+}
+
+/// カテゴリに所属するファイルを使用しているページ
+#[derive(Debug)]
+pub struct FileUsageSource {
+    files: Box<dyn MemberSource>,
+}
+
+impl FileUsageSource {
+    /// `files` が返すファイルを使用しているページを返す
+    pub fn new(files: Box<dyn MemberSource>) -> Self {
+        Self { files }
+    }
+}
+
+impl Default for FileUsageSource {
+    fn default() -> Self {
+        Self::new(Box::new(CategoryMemberSource))
+    }
+}
+
+impl MemberSource for FileUsageSource {
+    fn generate(&self, bot: &Bot, category: &str, namespaces: &[u32]) -> Receiver<Result<Page>> {
+        let files = self.files.generate(bot, category, &[NS_FILE]);
+        let bot = bot.clone();
+        let namespaces = namespaces.to_vec();
+        expand_pages(files, move |file| {
+            FileUsage::new(vec![file.title().to_string()])
+                .namespaces(namespaces.clone())
+                .generate(&bot)
+        })
+    }
+}
+
+/// カテゴリページへリンクしているページ
+#[derive(Debug)]
+pub struct BacklinkSource;
+
+impl MemberSource for BacklinkSource {
+    fn generate(&self, bot: &Bot, category: &str, namespaces: &[u32]) -> Receiver<Result<Page>> {
+        LinksHere::new(vec![category.to_string()])
+            .namespaces(namespaces.to_vec())
+            .generate(bot)
+    }
+}
+
+const NS_FILE: u32 = 6;
+const NS_TEMPLATE: u32 = 10;
+
+/// `seeds` のページごとに `expand` で取得したページを返す. `seeds` の取得中のエラーはそのまま返す
+fn expand_pages(
+    mut seeds: Receiver<Result<Page>>,
+    expand: impl Fn(Page) -> Receiver<Result<Page>> + Send + 'static,
+) -> Receiver<Result<Page>> {
+    let (tx, rx) = mpsc::channel(50);
+    tokio::spawn(async move {
+        while let Some(seed) = seeds.recv().await {
+            let mut pages = match seed {
+                Ok(seed) => expand(seed),
+                Err(err) => {
+                    let _ = tx.send(Err(err)).await;
+                    continue;
+                }
+            };
+            while let Some(page) = pages.recv().await {
+                if tx.send(page).await.is_err() {
+                    // Receiver hung up, just abort
+                    return;
+                }
+            }
+        }
+    });
+    rx
+}
+
+/// {{リダイレクトの所属カテゴリ}}でカテゴリを指定しているページ
+#[derive(Debug)]
+pub struct CategoryOfRedirectsSource;
+
+impl MemberSource for CategoryOfRedirectsSource {
+    fn generate(&self, bot: &Bot, category: &str, namespaces: &[u32]) -> Receiver<Result<Page>> {
+        let name = category.trim_start_matches("Category:");
+        Search::new(format!(
+            r#"hastemplate:"リダイレクトの所属カテゴリ" insource:"{name}""#
+        ))
+        .namespace(namespaces.to_vec())
+        .generate(bot)
+    }
+}
+
+/// コマンドの種類ごとに必要な取得元.
 /// [`CategoryMembers`] だけでは{{リダイレクトの所属カテゴリ}}などが取得できない.
+pub fn member_sources(command_type: &CommandType) -> Vec<Box<dyn MemberSource>> {
+    match command_type {
+        CommandType::Reassignment | CommandType::Remove | CommandType::Duplicate => vec![
+            Box::new(CategoryMemberSource),
+            Box::new(InsourceSource),
+            Box::new(CategoryOfRedirectsSource),
+            Box::<EmbeddedInSource>::default(),
+            Box::<FileUsageSource>::default(),
+        ],
+        // カテゴリページへリンクするテンプレートの引数でカテゴリを指定しているページも移動先に合わせる
+        CommandType::Move => vec![
+            Box::new(CategoryMemberSource),
+            Box::new(InsourceSource),
+            Box::new(CategoryOfRedirectsSource),
+            Box::<EmbeddedInSource>::default(),
+            Box::<FileUsageSource>::default(),
+            Box::new(BacklinkSource),
+        ],
+        // ソートキーはカテゴリタグにのみ指定されている
+        CommandType::SortKey => vec![Box::new(CategoryMemberSource), Box::new(InsourceSource)],
+    }
+}
+
+/// `sources` から取得したページを重複なく返す.
+/// 取得元には `Category:名前` の形に正規化したカテゴリ名を渡す.
+/// 取得中のエラーは取得し直し、それでも取得できなかった場合のみ `Err` を返す
 pub async fn list_category_members(
     bot: &Bot,
     category: impl Into<String>,
     namespaces: Vec<u32>,
+    sources: Vec<Box<dyn MemberSource>>,
 ) -> Receiver<Result<Page>> {
     let (tx, rx) = mpsc::channel(50);
 
     let category = category.into();
-    let bot = bot.clone();
+    let category = CategoryNormalizer::new(bot)
+        .normalize_name(&category)
+        .unwrap_or(category);
 
     let seen = Arc::new(Mutex::new(HashSet::<String>::new()));
 
-    let category_members = CategoryMembers::new(category.clone())
-        .namespace(namespaces.clone())
-        .generate(&bot);
-    send_categories(category_members, tx.clone(), seen.clone()).await;
-
-    let search = Search::new(format!(r#"insource:"{}""#, category.clone()))
-        .namespace(namespaces.clone())
-        .generate(&bot);
-    send_categories(search, tx.clone(), seen.clone()).await;
+    for source in sources {
+        send_categories(
+            source,
+            bot.clone(),
+            category.clone(),
+            namespaces.clone(),
+            tx.clone(),
+            seen.clone(),
+        )
+        .await;
+    }
 
     rx
 }
 
+/// 取得中にエラーとなった場合に取得し直す回数
+const LIST_MAX_RETRIES: usize = 3;
+
+/// たどった下位カテゴリの階層
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CategoryTree {
+    pub category: String,
+    pub children: Vec<CategoryTree>,
+    /// 操作を当てはめられず、所属ページを操作しなかった下位カテゴリか
+    pub skipped: bool,
+}
+
+impl CategoryTree {
+    /// 根のカテゴリを除く、全ての下位カテゴリ
+    pub fn descendants(&self) -> Vec<String> {
+        self.children
+            .iter()
+            .flat_map(|child| std::iter::once(child.category.clone()).chain(child.descendants()))
+            .collect()
+    }
+
+    /// `categories` に含まれる下位カテゴリを、操作しなかったものとする
+    pub fn mark_skipped(&mut self, categories: &[String]) {
+        for child in &mut self.children {
+            child.skipped = categories.contains(&child.category);
+            child.mark_skipped(categories);
+        }
+    }
+}
+
+/// `category` の下位カテゴリを `max_depth` 階層までたどる
+pub async fn traverse_subcategories(
+    bot: &Bot,
+    category: &str,
+    max_depth: usize,
+) -> anyhow::Result<CategoryTree> {
+    build_category_tree(category, max_depth, |category| async move {
+        let mut members = CategoryMembers::new(category)
+            .namespace(vec![14])
+            .generate(bot);
+        let mut subcategories = Vec::new();
+        while let Some(page) = members.recv().await {
+            subcategories.push(page?.title().to_string());
+        }
+        Ok(subcategories)
+    })
+    .await
+}
+
+/// `subcategories_of` で下位カテゴリを幅優先でたどる.
+/// 一度たどったカテゴリは再度たどらないため、カテゴリが循環していても終了する
+async fn build_category_tree<F, Fut>(
+    root: &str,
+    max_depth: usize,
+    mut subcategories_of: F,
+) -> anyhow::Result<CategoryTree>
+where
+    F: FnMut(String) -> Fut,
+    Fut: Future<Output = anyhow::Result<Vec<String>>>,
+{
+    let mut visited = HashSet::from([root.to_string()]);
+    // (カテゴリ, 親の位置)
+    let mut nodes = vec![(root.to_string(), None)];
+    // (位置, 深さ)
+    let mut queue = VecDeque::from([(0, 0)]);
+
+    while let Some((index, depth)) = queue.pop_front() {
+        if depth >= max_depth {
+            continue;
+        }
+
+        let category = nodes[index].0.clone();
+        for subcategory in subcategories_of(category).await? {
+            if visited.insert(subcategory.clone()) {
+                nodes.push((subcategory, Some(index)));
+                queue.push_back((nodes.len() - 1, depth + 1));
+            }
+        }
+    }
+
+    // 子は必ず親より後にあるため、後ろから親へ付け替える
+    let mut trees = nodes
+        .iter()
+        .map(|(category, _)| CategoryTree {
+            category: category.clone(),
+            ..Default::default()
+        })
+        .collect::<Vec<_>>();
+    for index in (1..nodes.len()).rev() {
+        let tree = std::mem::take(&mut trees[index]);
+        if let Some(parent) = nodes[index].1 {
+            trees[parent].children.insert(0, tree);
+        }
+    }
+
+    Ok(std::mem::take(&mut trees[0]))
+}
+
+/// 取得し直す場合も、既に送ったページは `seen` により重複して送らない
 async fn send_categories(
-    mut recv: Receiver<Result<Page>>,
+    source: Box<dyn MemberSource>,
+    bot: Bot,
+    category: String,
+    namespaces: Vec<u32>,
     tx: Sender<Result<Page>>,
     seen: Arc<Mutex<HashSet<String>>>,
 ) {
     tokio::spawn(async move {
-        while let Some(member) = recv.recv().await {
-            {
-                let mut seen = seen.lock().unwrap();
-                if let Ok(member) = &member {
+        let mut backoff = ExponentialBuilder::default()
+            .with_jitter()
+            .with_max_times(LIST_MAX_RETRIES)
+            .build();
+
+        loop {
+            let mut recv = source.generate(&bot, &category, &namespaces);
+            let err = loop {
+                let member = match recv.recv().await {
+                    Some(Ok(member)) => member,
+                    Some(Err(err)) => break err,
+                    None => return,
+                };
+                {
+                    let mut seen = seen.lock().unwrap();
                     if seen.contains(member.title()) {
                         continue;
                     }
 
                     seen.insert(member.title().to_string());
                 }
-            }
 
-            if tx.send(member).await.is_err() {
-                // Receiver hung up, just abort
+                if tx.send(Ok(member)).await.is_err() {
+                    // Receiver hung up, just abort
+                    return;
+                }
+            };
+
+            let Some(delay) = backoff.next() else {
+                let _ = tx.send(Err(err)).await;
                 return;
-            }
+            };
+            warn!(?source, ?err, "Error while listing members. Retrying");
+            time::sleep(delay).await;
         }
     });
 }
+
+#[cfg(test)]
+mod test {
+    use std::collections::{HashMap, HashSet};
+    use std::sync::{Arc, Mutex};
+
+    use mwbot::{Bot, Page, Result};
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+    use tokio::sync::mpsc::{self, Receiver};
+
+    use crate::db::CommandType;
+    use crate::generator::{
+        build_category_tree,
+        insource_query,
+        list_category_members,
+        member_sources,
+        CategoryTree,
+        EmbeddedInSource,
+        MemberSource,
+        RecordingSource,
+    };
+    use crate::util::test::bot;
+    use crate::QUEUE_PAGE;
+
+    /// 決まったページを返す取得元
+    #[derive(Debug)]
+    struct FixedSource(Vec<&'static str>);
+
+    impl MemberSource for FixedSource {
+        fn generate(
+            &self,
+            bot: &Bot,
+            _category: &str,
+            _namespaces: &[u32],
+        ) -> Receiver<Result<Page>> {
+            let (tx, rx) = mpsc::channel(self.0.len().max(1));
+            for title in &self.0 {
+                tx.try_send(bot.page(title)).unwrap();
+            }
+            rx
+        }
+    }
+
+    #[tokio::test]
+    async fn test_list_category_members_dedup() {
+        let bot = bot().await;
+        let sources: Vec<Box<dyn MemberSource>> = vec![
+            Box::new(FixedSource(vec!["テスト1", "テスト2"])),
+            Box::new(FixedSource(vec!["テスト2", "テスト3"])),
+        ];
+
+        let mut members = list_category_members(&bot, "Category:テスト", vec![0], sources).await;
+        let mut titles = Vec::new();
+        while let Some(page) = members.recv().await {
+            titles.push(page.unwrap().title().to_string());
+        }
+        titles.sort();
+
+        assert_eq!(titles, vec!["テスト1", "テスト2", "テスト3"]);
+    }
+
+    #[test]
+    fn test_insource_query() {
+        let names = ["カテゴリ".to_string(), "Category".to_string()];
+        assert_eq!(
+            insource_query("Category:日本", &names),
+            r#"insource:"日本" insource:/\[\[ *(カテゴリ|Category) *: *日本 *(\]|\|)/i"#
+        );
+        assert_eq!(
+            insource_query("Category:伊達市 (北海道)", &["Category".to_string()]),
+            r#"insource:"伊達市 (北海道)" insource:/\[\[ *(Category) *: *伊達市[ _]\(北海道\) *(\]|\|)/i"#
+        );
+    }
+
+    /// テンプレートが付与するカテゴリは、テンプレートを参照読み込みしているページまでたどる
+    #[tokio::test]
+    async fn test_embedded_in_source() {
+        let bot = bot().await;
+        let source = EmbeddedInSource::new(Box::new(FixedSource(vec!["Template:BOTREQ"])));
+
+        let mut pages = source.generate(&bot, "Category:テスト", &[4]);
+        let mut found = false;
+        while let Some(page) = pages.recv().await {
+            if page.unwrap().title() == QUEUE_PAGE {
+                found = true;
+                break;
+            }
+        }
+
+        assert!(found);
+    }
+
+    #[rstest]
+    #[case(CommandType::Reassignment, false)]
+    #[case(CommandType::Move, true)]
+    fn test_member_sources(#[case] command_type: CommandType, #[case] has_backlinks: bool) {
+        let sources = member_sources(&command_type)
+            .iter()
+            .map(|source| format!("{source:?}"))
+            .collect::<Vec<_>>();
+
+        assert!(sources
+            .iter()
+            .any(|source| source.starts_with("EmbeddedInSource")));
+        assert!(sources
+            .iter()
+            .any(|source| source.starts_with("FileUsageSource")));
+        assert_eq!(
+            sources.contains(&"BacklinkSource".to_string()),
+            has_backlinks
+        );
+    }
+
+    #[tokio::test]
+    async fn test_recording_source() {
+        let bot = bot().await;
+        let recorded = Arc::new(Mutex::new(HashSet::new()));
+        let sources: Vec<Box<dyn MemberSource>> = vec![
+            Box::new(RecordingSource::new(
+                Box::new(FixedSource(vec!["テスト1", "テスト2"])),
+                recorded.clone(),
+            )),
+            Box::new(FixedSource(vec!["テスト2", "テスト3"])),
+        ];
+
+        let mut members = list_category_members(&bot, "Category:テスト", vec![0], sources).await;
+        while members.recv().await.is_some() {}
+
+        let mut recorded = recorded.lock().unwrap().iter().cloned().collect::<Vec<_>>();
+        recorded.sort();
+        assert_eq!(recorded, ["テスト1", "テスト2"]);
+    }
+
+    fn tree(category: &str, children: Vec<CategoryTree>) -> CategoryTree {
+        CategoryTree {
+            category: category.to_string(),
+            children,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_build_category_tree() -> anyhow::Result<()> {
+        // C と D は互いに下位カテゴリになっている
+        let subcategories = HashMap::from([
+            ("A", vec!["B", "C"]),
+            ("B", vec!["C"]),
+            ("C", vec!["D"]),
+            ("D", vec!["C", "E"]),
+            ("E", vec!["F"]),
+        ]);
+        let subcategories_of = |category: String| {
+            let children = subcategories
+                .get(category.as_str())
+                .cloned()
+                .unwrap_or_default()
+                .into_iter()
+                .map(ToString::to_string)
+                .collect();
+            async move { Ok(children) }
+        };
+
+        let actual = build_category_tree("A", 3, subcategories_of).await?;
+
+        assert_eq!(
+            actual,
+            tree(
+                "A",
+                vec![
+                    tree("B", vec![]),
+                    tree("C", vec![tree("D", vec![tree("E", vec![])])]),
+                ],
+            )
+        );
+        assert_eq!(actual.descendants(), ["B", "C", "D", "E"]);
+
+        let mut marked = actual.clone();
+        marked.mark_skipped(&["C".to_string(), "E".to_string()]);
+        let skipped = |tree: &CategoryTree| tree.skipped;
+        assert!(!skipped(&marked.children[0]));
+        assert!(skipped(&marked.children[1]));
+        assert!(!skipped(&marked.children[1].children[0]));
+        assert!(skipped(&marked.children[1].children[0].children[0]));
+
+        let actual = build_category_tree("A", 0, subcategories_of).await?;
+        assert_eq!(actual, tree("A", vec![]));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,592 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use bzip2::read::MultiBzDecoder;
+use flate2::read::MultiGzDecoder;
+use mwbot::{Bot, Error, Page, Result};
+use quick_xml::events::Event;
+use regex::Regex;
+use tokio::sync::mpsc::{self, Receiver, Sender};
+
+use crate::config::DumpFiles;
+use crate::generator::MemberSource;
+use crate::title::CategoryNormalizer;
+
+/// categorylinksとpageのSQLダンプに記録された所属ページ
+#[derive(Debug)]
+pub struct SqlDumpSource {
+    categorylinks: PathBuf,
+    page: PathBuf,
+}
+
+impl SqlDumpSource {
+    pub fn new(categorylinks: impl Into<PathBuf>, page: impl Into<PathBuf>) -> Self {
+        Self {
+            categorylinks: categorylinks.into(),
+            page: page.into(),
+        }
+    }
+}
+
+impl MemberSource for SqlDumpSource {
+    fn generate(&self, bot: &Bot, category: &str, namespaces: &[u32]) -> Receiver<Result<Page>> {
+        let (tx, rx) = mpsc::channel(50);
+        let bot = bot.clone();
+        let categorylinks = self.categorylinks.clone();
+        let page = self.page.clone();
+        let category = category.to_string();
+        let namespaces = namespaces.to_vec();
+        tokio::task::spawn_blocking(move || {
+            // categorylinksには名前空間を除いたカテゴリ名が `_` 区切りで記録されている
+            let dbkey = match bot.title_codec().new_title(&category) {
+                Ok(title) => title.dbkey().to_string(),
+                Err(err) => {
+                    let _ = tx.blocking_send(Err(err.into()));
+                    return;
+                }
+            };
+            let index = load_index(&[&categorylinks, &page], || {
+                read_sql_index(open_dump(&categorylinks)?, open_dump(&page)?)
+            });
+            let members = index.map(|index| index.members(&dbkey, &namespaces));
+            send_members(&tx, members, |(namespace, title)| {
+                bot.page_from_database(namespace, &title)
+            });
+        });
+        rx
+    }
+
+    fn lists_members_only(&self) -> bool {
+        true
+    }
+}
+
+/// pages-articlesのXMLダンプの本文にカテゴリタグがあるページ.
+/// テンプレートにより付与されるカテゴリは本文からは分からないため含まない
+#[derive(Debug)]
+pub struct XmlDumpSource {
+    pages_articles: PathBuf,
+}
+
+impl XmlDumpSource {
+    pub fn new(pages_articles: impl Into<PathBuf>) -> Self {
+        Self {
+            pages_articles: pages_articles.into(),
+        }
+    }
+}
+
+impl MemberSource for XmlDumpSource {
+    fn generate(&self, bot: &Bot, category: &str, namespaces: &[u32]) -> Receiver<Result<Page>> {
+        let (tx, rx) = mpsc::channel(50);
+        let bot = bot.clone();
+        let pages_articles = self.pages_articles.clone();
+        let category = category.to_string();
+        let namespaces = namespaces.to_vec();
+        tokio::task::spawn_blocking(move || {
+            let normalizer = CategoryNormalizer::new(&bot);
+            let index = load_index(&[&pages_articles], || {
+                read_xml_index(open_dump(&pages_articles)?, |link| {
+                    // 名前空間のないリンクは記事へのリンクのため、カテゴリ名前空間のリンクのみ数える
+                    normalizer
+                        .normalize(link)
+                        .and_then(|link| normalizer.comparison_key(&link))
+                })
+            });
+            let members = index.map(|index| {
+                normalizer
+                    .comparison_key(&category)
+                    .map(|key| index.members(&key, &namespaces))
+                    .unwrap_or_default()
+            });
+            send_members(&tx, members, |(_, title)| bot.page(&title));
+        });
+        rx
+    }
+
+    fn lists_members_only(&self) -> bool {
+        true
+    }
+}
+
+/// 設定されたダンプの取得元
+pub fn dump_source(files: &DumpFiles) -> Box<dyn MemberSource> {
+    match files {
+        DumpFiles::Sql {
+            categorylinks,
+            page,
+        } => Box::new(SqlDumpSource::new(categorylinks, page)),
+        DumpFiles::Xml { pages_articles } => Box::new(XmlDumpSource::new(pages_articles)),
+    }
+}
+
+/// ダンプの時点と比べた所属ページの違い
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DumpDiff {
+    /// ダンプの時点では所属していなかったページ
+    pub appeared: Vec<String>,
+    /// ダンプの時点では所属していたが、現在は所属していないページ
+    pub disappeared: Vec<String>,
+}
+
+impl DumpDiff {
+    pub fn new(live: &HashSet<String>, snapshot: &HashSet<String>) -> Self {
+        let mut appeared = live.difference(snapshot).cloned().collect::<Vec<_>>();
+        let mut disappeared = snapshot.difference(live).cloned().collect::<Vec<_>>();
+        appeared.sort();
+        disappeared.sort();
+        Self {
+            appeared,
+            disappeared,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.appeared.is_empty() && self.disappeared.is_empty()
+    }
+}
+
+/// カテゴリごとの所属ページを引けるようにしたダンプ
+#[derive(Debug, Default)]
+struct DumpIndex {
+    /// カテゴリのキーごとの所属ページのID. キーの形はダンプの形式による
+    categories: HashMap<String, Vec<u32>>,
+    /// ページのIDごとの名前空間とタイトル
+    pages: HashMap<u32, (i32, String)>,
+}
+
+impl DumpIndex {
+    /// `key` のカテゴリに所属する `namespaces` のページの名前空間とタイトル
+    fn members(&self, key: &str, namespaces: &[u32]) -> Vec<(i32, String)> {
+        self.categories
+            .get(key)
+            .into_iter()
+            .flatten()
+            .filter_map(|id| self.pages.get(id))
+            .filter(|(namespace, _)| {
+                u32::try_from(*namespace).is_ok_and(|namespace| namespaces.contains(&namespace))
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+/// 読み込み済みのダンプ. ダンプは大きいため、1回の実行で同じファイルは1度だけ読み込む
+static LOADED_INDEXES: OnceLock<Mutex<HashMap<Vec<PathBuf>, Arc<DumpIndex>>>> = OnceLock::new();
+
+/// `paths` のダンプを読み込む. 読み込み済みの場合は `load` を呼ばずに返す
+fn load_index(
+    paths: &[&Path],
+    load: impl FnOnce() -> io::Result<DumpIndex>,
+) -> io::Result<Arc<DumpIndex>> {
+    let paths = paths
+        .iter()
+        .map(|path| path.to_path_buf())
+        .collect::<Vec<_>>();
+    // 同時に読み込み始めないよう、読み込みの間もロックを保持する
+    let mut loaded = LOADED_INDEXES.get_or_init(Default::default).lock().unwrap();
+    if let Some(index) = loaded.get(&paths) {
+        return Ok(index.clone());
+    }
+    let index = Arc::new(load()?);
+    loaded.insert(paths, index.clone());
+    Ok(index)
+}
+
+/// ダンプのファイルを開く. 拡張子が `.gz` や `.bz2` の場合は展開しながら読む
+fn open_dump(path: &Path) -> io::Result<Box<dyn BufRead>> {
+    let file = File::open(path)?;
+    Ok(decompress(path.extension().and_then(OsStr::to_str), file))
+}
+
+fn decompress(extension: Option<&str>, reader: impl Read + 'static) -> Box<dyn BufRead> {
+    match extension {
+        Some("gz") => Box::new(BufReader::new(MultiGzDecoder::new(reader))),
+        // Wikimediaのダンプは複数のストリームに分かれている
+        Some("bz2") => Box::new(BufReader::new(MultiBzDecoder::new(reader))),
+        _ => Box::new(BufReader::new(reader)),
+    }
+}
+
+fn send_members<T>(
+    tx: &Sender<Result<Page>>,
+    members: io::Result<Vec<T>>,
+    to_page: impl Fn(T) -> Result<Page>,
+) {
+    let members = match members {
+        Ok(members) => members,
+        Err(err) => {
+            let _ = tx.blocking_send(Err(Error::IoError(err)));
+            return;
+        }
+    };
+    for member in members {
+        if tx.blocking_send(to_page(member)).is_err() {
+            // Receiver hung up, just abort
+            return;
+        }
+    }
+}
+
+/// SQLダンプの1行分の値. `NULL` は `None` になる
+type SqlRow = Vec<Option<Vec<u8>>>;
+
+/// categorylinksとpageのSQLダンプを読み込む. カテゴリのキーは `_` 区切りのカテゴリ名
+fn read_sql_index(categorylinks: impl BufRead, page: impl BufRead) -> io::Result<DumpIndex> {
+    let mut index = DumpIndex::default();
+
+    // (cl_from, cl_to, ...)
+    for_each_sql_row(categorylinks, "categorylinks", |row| {
+        let (Some(id), Some(Some(category))) = (sql_integer(row, 0), row.get(1)) else {
+            return;
+        };
+        index
+            .categories
+            .entry(String::from_utf8_lossy(category).into_owned())
+            .or_default()
+            .push(id as u32);
+    })?;
+
+    // (page_id, page_namespace, page_title, ...)
+    for_each_sql_row(page, "page", |row| {
+        let (Some(id), Some(namespace), Some(Some(title))) =
+            (sql_integer(row, 0), sql_integer(row, 1), row.get(2))
+        else {
+            return;
+        };
+        index.pages.insert(
+            id as u32,
+            (
+                namespace as i32,
+                String::from_utf8_lossy(title).into_owned(),
+            ),
+        );
+    })?;
+
+    Ok(index)
+}
+
+/// `table` への `INSERT` 文の各行について `f` を呼ぶ.
+/// ソートキーなどはUTF-8とは限らないため、バイト列のまま読む
+fn for_each_sql_row(
+    mut reader: impl BufRead,
+    table: &str,
+    mut f: impl FnMut(&SqlRow),
+) -> io::Result<()> {
+    let prefix = format!("INSERT INTO `{table}` VALUES ");
+    let mut line = Vec::new();
+    while reader.read_until(b'\n', &mut line)? > 0 {
+        if let Some(values) = line.strip_prefix(prefix.as_bytes()) {
+            for row in parse_sql_values(values) {
+                f(&row);
+            }
+        }
+        line.clear();
+    }
+    Ok(())
+}
+
+/// `(1,'a'),(2,NULL);` の形式の値を行ごとに分ける
+fn parse_sql_values(values: &[u8]) -> Vec<SqlRow> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut value = Vec::new();
+    let mut is_quoted = false;
+    let mut in_row = false;
+    let mut in_string = false;
+
+    let mut bytes = values.iter().copied();
+    while let Some(byte) = bytes.next() {
+        if in_string {
+            match byte {
+                b'\\' => match bytes.next() {
+                    Some(b'0') => value.push(0),
+                    Some(b'n') => value.push(b'\n'),
+                    Some(b'r') => value.push(b'\r'),
+                    Some(b't') => value.push(b'\t'),
+                    Some(b'Z') => value.push(0x1a),
+                    Some(escaped) => value.push(escaped),
+                    None => break,
+                },
+                b'\'' => in_string = false,
+                _ => value.push(byte),
+            }
+            continue;
+        }
+
+        match byte {
+            b'(' if !in_row => in_row = true,
+            b'\'' if in_row => {
+                in_string = true;
+                is_quoted = true;
+            }
+            b',' | b')' if in_row => {
+                let value = std::mem::take(&mut value);
+                row.push((is_quoted || value != b"NULL").then_some(value));
+                is_quoted = false;
+                if byte == b')' {
+                    rows.push(std::mem::take(&mut row));
+                    in_row = false;
+                }
+            }
+            _ if in_row => value.push(byte),
+            _ => {}
+        }
+    }
+
+    rows
+}
+
+fn sql_integer(row: &SqlRow, index: usize) -> Option<i64> {
+    let value = row.get(index)?.as_deref()?;
+    std::str::from_utf8(value).ok()?.parse().ok()
+}
+
+/// pages-articlesのXMLダンプを読み込む.
+/// 本文のカテゴリタグのリンク先を `category_key` でカテゴリのキーにし、カテゴリタグでないリンクは `None` とする
+fn read_xml_index(
+    reader: impl BufRead,
+    category_key: impl Fn(&str) -> Option<String>,
+) -> io::Result<DumpIndex> {
+    let mut index = DumpIndex::default();
+    let mut reader = quick_xml::Reader::from_reader(reader);
+    let mut buf = Vec::new();
+    // 読んでいる要素の名前
+    let mut elements = Vec::<Vec<u8>>::new();
+    let (mut title, mut namespace, mut text) = (String::new(), String::new(), String::new());
+    let mut id = 0;
+    loop {
+        match reader.read_event_into(&mut buf).map_err(xml_error)? {
+            Event::Start(start) => {
+                let name = start.name().as_ref().to_vec();
+                match name.as_slice() {
+                    b"page" => {
+                        title.clear();
+                        namespace.clear();
+                        text.clear();
+                    }
+                    b"text" => text.clear(),
+                    _ => {}
+                }
+                elements.push(name);
+            }
+            Event::Text(content) => {
+                let content = content.unescape().map_err(xml_error)?;
+                match elements.last().map(Vec::as_slice) {
+                    Some(b"title") => title.push_str(&content),
+                    Some(b"ns") => namespace.push_str(&content),
+                    Some(b"text") => text.push_str(&content),
+                    _ => {}
+                }
+            }
+            Event::CData(content) => {
+                if elements.last().map(Vec::as_slice) == Some(b"text") {
+                    text.push_str(&String::from_utf8_lossy(&content));
+                }
+            }
+            Event::End(_) => {
+                if elements.pop().as_deref() != Some(b"page") {
+                    continue;
+                }
+                let Ok(namespace) = namespace.trim().parse::<i32>() else {
+                    continue;
+                };
+                let categories = category_tag_targets(&text)
+                    .filter_map(&category_key)
+                    .collect::<HashSet<_>>();
+                if categories.is_empty() {
+                    continue;
+                }
+                id += 1;
+                for category in categories {
+                    index.categories.entry(category).or_default().push(id);
+                }
+                index.pages.insert(id, (namespace, title.clone()));
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    Ok(index)
+}
+
+fn xml_error(err: impl Into<quick_xml::Error>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.into())
+}
+
+/// 本文の内部リンクのリンク先. 名前空間は確かめないため、カテゴリタグかは呼び出し側で判断する.
+/// `[[:Category:名前]]` はカテゴリへのリンクのため除く
+pub(crate) fn category_tag_targets(text: &str) -> impl Iterator<Item = &str> {
+    static CATEGORY_TAG: OnceLock<Regex> = OnceLock::new();
+    CATEGORY_TAG
+        .get_or_init(|| {
+            Regex::new(r"\[\[\s*([^:\[\]|\n][^\[\]|\n]*)(?:\|[^\[\]]*)?\]\]").expect("valid regex")
+        })
+        .captures_iter(text)
+        .filter_map(|captures| captures.get(1))
+        .map(|target| target.as_str().trim())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+    use std::io::{Read, Write};
+
+    use bzip2::write::BzEncoder;
+    use flate2::write::GzEncoder;
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+
+    use crate::generator::dump::{
+        decompress,
+        parse_sql_values,
+        read_sql_index,
+        read_xml_index,
+        DumpDiff,
+    };
+
+    #[test]
+    fn test_parse_sql_values() {
+        let rows = parse_sql_values(br"(1,'a,b',NULL),(2,'It\'s (x)','NULL');");
+        assert_eq!(
+            rows,
+            vec![
+                vec![Some(b"1".to_vec()), Some(b"a,b".to_vec()), None],
+                vec![
+                    Some(b"2".to_vec()),
+                    Some(b"It's (x)".to_vec()),
+                    Some(b"NULL".to_vec())
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_sql_index() {
+        let categorylinks = indoc! {r"
+            INSERT INTO `categorylinks` VALUES (1,'テスト','A','2024-01-01 00:00:00','','uppercase','page'),(2,'テスト','B','2024-01-01 00:00:00','','uppercase','page');
+            INSERT INTO `categorylinks` VALUES (3,'テスト_(2)','C','2024-01-01 00:00:00','','uppercase','page'),(4,'テスト','D','2024-01-01 00:00:00','','uppercase','subcat');
+        "};
+        let page = indoc! {r"
+            INSERT INTO `page` VALUES (1,0,'記事_1',0,0,0.5,'20240101000000',NULL,1,10,'wikitext',NULL),(2,1,'記事_1',0,0,0.5,'20240101000000',NULL,2,10,'wikitext',NULL);
+            INSERT INTO `page` VALUES (3,0,'記事_3',0,0,0.5,'20240101000000',NULL,3,10,'wikitext',NULL),(4,14,'下位',0,0,0.5,'20240101000000',NULL,4,10,'wikitext',NULL);
+        "};
+
+        let index = read_sql_index(categorylinks.as_bytes(), page.as_bytes()).unwrap();
+        assert_eq!(
+            index.members("テスト", &[0, 14]),
+            vec![(0, "記事_1".to_string()), (14, "下位".to_string())]
+        );
+        assert_eq!(
+            index.members("テスト_(2)", &[0, 14]),
+            vec![(0, "記事_3".to_string())]
+        );
+        assert!(index.members("テスト", &[2]).is_empty());
+    }
+
+    #[test]
+    fn test_read_xml_index() {
+        let dump = indoc! {r#"
+            <mediawiki>
+              <page>
+                <title>記事1</title>
+                <ns>0</ns>
+                <revision>
+                  <text bytes="10" xml:space="preserve">本文
+            [[Category:テスト|あ]]</text>
+                </revision>
+              </page>
+              <page>
+                <title>記事2 &amp; 記事3</title>
+                <ns>0</ns>
+                <revision>
+                  <text bytes="10" xml:space="preserve">[[:Category:テスト]]</text>
+                </revision>
+              </page>
+              <page>
+                <title>ノート:記事1</title>
+                <ns>1</ns>
+                <revision>
+                  <text bytes="10" xml:space="preserve">[[Category:テスト]]</text>
+                </revision>
+              </page>
+              <page>
+                <title>記事4 &amp; 記事5</title>
+                <ns>0</ns>
+                <revision>
+                  <text bytes="10" xml:space="preserve">&lt;!-- --&gt;
+            本文
+            [[ カテゴリ:テスト ]]
+            [[Category:別のテスト]]</text>
+                </revision>
+              </page>
+              <page>
+                <title>記事6</title>
+                <ns>0</ns>
+                <revision>
+                  <text bytes="0" />
+                </revision>
+              </page>
+            </mediawiki>
+        "#};
+
+        let index = read_xml_index(dump.as_bytes(), |link| {
+            link.strip_prefix("Category:")
+                .or_else(|| link.strip_prefix("カテゴリ:"))
+                .map(ToString::to_string)
+        })
+        .unwrap();
+        assert_eq!(
+            index.members("テスト", &[0]),
+            vec![(0, "記事1".to_string()), (0, "記事4 & 記事5".to_string())]
+        );
+        assert_eq!(
+            index.members("テスト", &[1]),
+            vec![(1, "ノート:記事1".to_string())]
+        );
+        assert_eq!(
+            index.members("別のテスト", &[0]),
+            vec![(0, "記事4 & 記事5".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_decompress() {
+        let dump = "INSERT INTO `page` VALUES (1,0,'記事',0);\n";
+
+        let mut gz = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(dump.as_bytes()).unwrap();
+        let mut bz = BzEncoder::new(Vec::new(), bzip2::Compression::default());
+        bz.write_all(dump.as_bytes()).unwrap();
+
+        for (extension, compressed) in [
+            (Some("gz"), gz.finish().unwrap()),
+            (Some("bz2"), bz.finish().unwrap()),
+            (Some("sql"), dump.as_bytes().to_vec()),
+        ] {
+            let mut decompressed = String::new();
+            decompress(extension, std::io::Cursor::new(compressed))
+                .read_to_string(&mut decompressed)
+                .unwrap();
+            assert_eq!(decompressed, dump);
+        }
+    }
+
+    #[test]
+    fn test_dump_diff() {
+        let live = HashSet::from(["A".to_string(), "B".to_string(), "D".to_string()]);
+        let snapshot = HashSet::from(["A".to_string(), "C".to_string(), "E".to_string()]);
+
+        let diff = DumpDiff::new(&live, &snapshot);
+        assert_eq!(diff.appeared, vec!["B", "D"]);
+        assert_eq!(diff.disappeared, vec!["C", "E"]);
+        assert!(!diff.is_empty());
+        assert!(DumpDiff::new(&live, &live).is_empty());
+    }
+}
@@ -1,5 +1,4 @@
-#![feature(type_alias_impl_trait)]
-
+use std::borrow::Cow;
 use std::fmt::Display;
 
 use backon::{ExponentialBuilder, Retryable};
@@ -11,20 +10,25 @@ use tap::Tap as _;
 use tracing::warn;
 use ulid::Ulid;
 
-use crate::command::OperationResult;
+use crate::command::{OperationResult, OperationStatus};
+use crate::generator::CategoryTree;
 use crate::util::{DateTimeProvider, IntoWikicode as _, ListExt as _, UtcDateTimeProvider};
 
 pub mod action;
+pub mod analysis;
 pub mod command;
 pub mod config;
 pub mod db;
 pub mod generator;
 pub mod replacer;
+pub mod title;
 pub mod util;
 
 pub const BOT_NAME: &str = "QueueBot";
 pub const QUEUE_PAGE: &str = "プロジェクト:カテゴリ関連/キュー";
 pub const EMERGENCY_STOP_PAGE: &str = "プロジェクト:カテゴリ関連/キュー/緊急停止";
+pub const TRIAL_PAGE_PREFIX: &str = "プロジェクト:カテゴリ関連/キュー/試行";
+pub const EDIT_REQUEST_PAGE_PREFIX: &str = "プロジェクト:カテゴリ関連/キュー/編集依頼";
 
 /// `動作中` と書かれていたら: 動作する (returns false)
 /// それ以外(例: `緊急停止`)なら: 止める (returns true)
@@ -96,12 +100,16 @@ fn get_signature<D: DateTimeProvider>(datetime_provider: D) -> Wikicode {
     signature
 }
 
+#[allow(clippy::too_many_arguments)]
 fn format_message<'i, I: WikinodeIterator, D: DateTimeProvider>(
     wikicode: &'i I,
     id: Option<&Ulid>,
     result: impl Into<String>,
     message: impl Into<String> + Display,
+    link: Option<&str>,
     statuses: Option<IndexMap<String, OperationResult>>,
+    tree: Option<&CategoryTree>,
+    disappeared: Option<&[String]>,
     datetime_provider: D,
 ) -> &'i I {
     let botreq = Template::new(
@@ -112,27 +120,34 @@ fn format_message<'i, I: WikinodeIterator, D: DateTimeProvider>(
     )
     .expect("unhappened");
 
-    let errors = statuses.map(|statuses| {
+    let details = statuses.map(|statuses| {
         statuses
             .iter()
-            .filter_map(|(page, status)| {
-                if let Err(err) = status {
-                    Some((page, err))
-                } else {
-                    None
-                }
+            .filter_map(|(page, status)| match status {
+                Err(err) => Some((page, Cow::Borrowed(err.as_str()))),
+                Ok(status) => status.report_message().map(|message| (page, message)),
             })
-            .map(|(page, error)| {
+            .map(|(page, message)| {
                 let wikicode = Wikicode::new("");
                 let wikilink = WikiLink::new(page, &Wikicode::new_text(page));
                 wikicode.append(&wikilink);
-                wikicode.append(&Wikicode::new_text(&format!(" - {error}")));
+                wikicode.append(&Wikicode::new_text(&format!(" - {message}")));
 
                 wikicode
             })
             .collect_to_ol()
     });
 
+    let tree = tree.map(|tree| {
+        let list = Wikicode::new_node("ul");
+        list.append(&format_category_tree(tree));
+        list
+    });
+
+    let disappeared = disappeared
+        .filter(|disappeared| !disappeared.is_empty())
+        .map(format_disappeared);
+
     let id = id.map(|id| format!("(ID: {id})").into_wikicode());
 
     let message = format!(" {message}").into_wikicode();
@@ -143,24 +158,87 @@ fn format_message<'i, I: WikinodeIterator, D: DateTimeProvider>(
         wikicode.append(&id);
     }
     wikicode.append(&message);
-    if let Some(errors) = errors {
-        wikicode.append(&errors);
+    if let Some(link) = link {
+        wikicode.append(&Wikicode::new_text(" "));
+        wikicode.append(&WikiLink::new(link, &Wikicode::new_text(link)));
+    }
+    if let Some(tree) = tree {
+        wikicode.append(&tree);
+    }
+    if let Some(disappeared) = disappeared {
+        wikicode.append(&disappeared);
+    }
+    if let Some(details) = details {
+        wikicode.append(&details);
     }
     wikicode.append(&signature);
 
     wikicode
 }
 
+/// 下位カテゴリの階層を入れ子の箇条書きの項目にする
+fn format_category_tree(tree: &CategoryTree) -> Wikicode {
+    let item = Wikicode::new_node("li");
+    item.append(&WikiLink::new(
+        &format!(":{}", tree.category),
+        &Wikicode::new_text(&tree.category),
+    ));
+    if tree.skipped {
+        item.append(&Wikicode::new_text(
+            " - 名前に操作元のカテゴリ名を含まないため、所属ページを操作しませんでした",
+        ));
+    }
+    if !tree.children.is_empty() {
+        let children = Wikicode::new_node("ul");
+        tree.children
+            .iter()
+            .for_each(|child| children.append(&format_category_tree(child)));
+        item.append(&children);
+    }
+    item
+}
+
+/// ダンプの時点では所属していたページを、結果の一覧とは別の箇条書きにする
+fn format_disappeared(disappeared: &[String]) -> Wikicode {
+    let pages = Wikicode::new_node("ul");
+    for page in disappeared {
+        let item = Wikicode::new_node("li");
+        item.append(&WikiLink::new(page, &Wikicode::new_text(page)));
+        pages.append(&item);
+    }
+
+    let item = Wikicode::new_node("li");
+    item.append(&Wikicode::new_text("ダンプの時点では所属していたページ"));
+    item.append(&pages);
+    let list = Wikicode::new_node("ul");
+    list.append(&item);
+    list
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn send_command_message(
     id: Option<&Ulid>,
     page: Page,
     section: &Section,
     result: impl Into<String>,
     message: impl Into<String> + Display,
+    link: Option<&str>,
     statuses: Option<IndexMap<String, OperationResult>>,
+    tree: Option<&CategoryTree>,
+    disappeared: Option<&[String]>,
 ) -> anyhow::Result<Page> {
     let [result, message] = [result.into(), message.into()];
-    let section = format_message(section, id, result, &message, statuses, UtcDateTimeProvider);
+    let section = format_message(
+        section,
+        id,
+        result,
+        &message,
+        link,
+        statuses,
+        tree,
+        disappeared,
+        UtcDateTimeProvider,
+    );
 
     let save = || async {
         let page = page.clone();
@@ -183,6 +261,125 @@ pub async fn send_command_message(
     Ok(page)
 }
 
+/// 試行結果をページごとの差分として整形する
+fn format_trial_preview(id: &Ulid, statuses: &IndexMap<String, OperationResult>) -> String {
+    let changed = statuses
+        .values()
+        .filter(|status| matches!(status, Ok(OperationStatus::Previewed { .. })))
+        .count();
+
+    let mut preview = format!("試行 (ID: {id}) の結果、{changed}件のページが変更されます。\n");
+    for (page, status) in statuses {
+        match status {
+            Ok(OperationStatus::Previewed { diff, changes }) => {
+                preview.push_str(&format!("\n== [[:{page}]] ==\n"));
+                // 変更内容のカテゴリタグで試行結果のページが分類されないようにする
+                for change in changes {
+                    preview.push_str(&format!(
+                        "* {}: <nowiki>{}</nowiki>\n",
+                        change.replacer, change.description
+                    ));
+                }
+                preview.push_str(&format!(
+                    "<syntaxhighlight lang=\"diff\">\n{diff}\n</syntaxhighlight>\n"
+                ));
+            }
+            Err(err) => {
+                preview.push_str(&format!("\n== [[:{page}]] ==\nエラー: {err}\n"));
+            }
+            Ok(status) => {
+                if let Some(message) = status.report_message() {
+                    preview.push_str(&format!("\n== [[:{page}]] ==\n{message}\n"));
+                }
+            }
+        }
+    }
+
+    preview
+}
+
+/// 試行結果をサブページに投稿し、投稿先のページ名を返す
+pub async fn send_trial_preview(
+    bot: &Bot,
+    id: &Ulid,
+    statuses: &IndexMap<String, OperationResult>,
+) -> anyhow::Result<String> {
+    let title = format!("{TRIAL_PAGE_PREFIX}/{id}");
+    let preview = format_trial_preview(id, statuses);
+
+    let save = || async {
+        bot.page(&title)?
+            .save(
+                preview.clone(),
+                &SaveOptions::summary(&format!("BOT: 試行結果を投稿 (ID: {id})")),
+            )
+            .await
+    };
+    save.retry(
+        &ExponentialBuilder::default()
+            .with_jitter()
+            .with_max_times(5),
+    )
+    .await?;
+
+    Ok(title)
+}
+
+/// 保護されていて編集できなかったページについて、管理者への編集依頼を作る.
+/// 該当するページがない場合は `None` を返す
+fn format_edit_request(
+    id: &Ulid,
+    summary: &str,
+    statuses: &IndexMap<String, OperationResult>,
+) -> Option<String> {
+    let protected = statuses
+        .iter()
+        .filter(|(_, status)| matches!(status, Ok(OperationStatus::Protected)))
+        .map(|(page, _)| format!("* [[:{page}]]\n"))
+        .collect::<Vec<_>>();
+    if protected.is_empty() {
+        return None;
+    }
+
+    let request = summary.trim_start_matches("BOT:").trim();
+    Some(format!(
+        "以下のページは保護されているため、{BOT_NAME}では編集できませんでした (ID: {id})。\n\
+         管理者の方は次の内容で編集をお願いします: {request}\n\n{}",
+        protected.concat()
+    ))
+}
+
+/// 保護されたページの編集依頼をサブページに投稿し、投稿先のページ名を返す.
+/// 該当するページがない場合は投稿せずに `None` を返す
+pub async fn send_edit_request(
+    bot: &Bot,
+    id: &Ulid,
+    summary: &str,
+    statuses: &IndexMap<String, OperationResult>,
+) -> anyhow::Result<Option<String>> {
+    let Some(request) = format_edit_request(id, summary, statuses) else {
+        return Ok(None);
+    };
+    let title = format!("{EDIT_REQUEST_PAGE_PREFIX}/{id}");
+
+    let save = || async {
+        bot.page(&title)?
+            .save(
+                request.clone(),
+                &SaveOptions::summary(&format!("BOT: 保護されたページの編集依頼 (ID: {id})")),
+            )
+            .await
+    };
+    save.retry(
+        &ExponentialBuilder::default()
+            .with_jitter()
+            .with_max_times(5),
+    )
+    .await?;
+
+    Ok(Some(title))
+}
+
 #[cfg(test)]
 mod test {
     use chrono::{DateTime, TimeZone, Utc};
@@ -191,8 +388,17 @@ mod test {
     use mwbot::parsoid::Wikicode;
     use ulid::Ulid;
 
+    use crate::command::OperationStatus;
+    use crate::generator::CategoryTree;
+    use crate::replacer::ChangeRecord;
     use crate::util::test;
-    use crate::{format_message, get_signature, DateTimeProvider};
+    use crate::{
+        format_edit_request,
+        format_message,
+        format_trial_preview,
+        get_signature,
+        DateTimeProvider,
+    };
 
     struct CustomDateTimeProvider(DateTime<Utc>);
     impl DateTimeProvider for CustomDateTimeProvider {
@@ -232,7 +438,10 @@ mod test {
             Some(&Ulid::from_string("01HCZ2CQPV5HW8NJAH6V1Z3KG9").unwrap()),
             "完了",
             "10件の操作が完了しました",
+            None,
             Some(IndexMap::new()),
+            None,
+            None,
             CustomDateTimeProvider(datetime),
         );
 
@@ -263,10 +472,13 @@ mod test {
             Some(&Ulid::from_string("01HCZ2CQPV5HW8NJAH6V1Z3KG9").unwrap()),
             "完了",
             "10件の操作が完了しました",
+            None,
             Some(indexmap! {
                 "テスト".to_string() => Err("これはエラーです".to_string()),
                 "テスト2".to_string() => Err("これはエラーです2".to_string()),
             }),
+            None,
+            None,
             CustomDateTimeProvider(datetime),
         );
 
@@ -286,4 +498,206 @@ mod test {
             --[[User:QueueBot|QueueBot]]<small><span class="plainlinks">([[Special:Contributions/QueueBot|投稿]]/[{{fullurl:Special:Log/delete|user=QueueBot}} 削除]/[{{fullurl:Special:Log/move|user=QueueBot}} 移動])</span></small> 2023年10月17日 (火) 00:00 (UTC)"#}
         );
     }
+
+    #[tokio::test]
+    async fn test_format_message_with_excluded() {
+        let bot = test::bot().await;
+
+        let datetime = Utc.with_ymd_and_hms(2023, 10, 17, 0, 0, 0).unwrap();
+
+        let wikicode = Wikicode::new("");
+        format_message(
+            &wikicode,
+            Some(&Ulid::from_string("01HCZ2CQPV5HW8NJAH6V1Z3KG9").unwrap()),
+            "完了",
+            "1件の操作を完了しました",
+            None,
+            Some(indexmap! {
+                "テスト".to_string() => Ok(OperationStatus::Done),
+                "テスト2".to_string() => Ok(OperationStatus::Excluded),
+            }),
+            None,
+            None,
+            CustomDateTimeProvider(datetime),
+        );
+
+        let wikitext = bot
+            .parsoid()
+            .transform_to_wikitext(&wikicode)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            &wikitext,
+            indoc! {r#"
+            {{BOTREQ|完了}}(ID: 01HCZ2CQPV5HW8NJAH6V1Z3KG9) 1件の操作を完了しました
+            
+            # [[テスト2]] - 除外しました
+            --[[User:QueueBot|QueueBot]]<small><span class="plainlinks">([[Special:Contributions/QueueBot|投稿]]/[{{fullurl:Special:Log/delete|user=QueueBot}} 削除]/[{{fullurl:Special:Log/move|user=QueueBot}} 移動])</span></small> 2023年10月17日 (火) 00:00 (UTC)"#}
+        );
+    }
+
+    #[tokio::test]
+    async fn test_format_message_with_tree() {
+        let bot = test::bot().await;
+
+        let datetime = Utc.with_ymd_and_hms(2023, 10, 17, 0, 0, 0).unwrap();
+
+        let tree = CategoryTree {
+            category: "Category:Name1".to_string(),
+            children: vec![
+                CategoryTree {
+                    category: "Category:Name2".to_string(),
+                    children: vec![CategoryTree {
+                        category: "Category:Name3".to_string(),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                },
+                CategoryTree {
+                    category: "Category:Other".to_string(),
+                    skipped: true,
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let wikicode = Wikicode::new("");
+        format_message(
+            &wikicode,
+            Some(&Ulid::from_string("01HCZ2CQPV5HW8NJAH6V1Z3KG9").unwrap()),
+            "完了",
+            "1件の操作を完了しました",
+            None,
+            Some(IndexMap::new()),
+            Some(&tree),
+            None,
+            CustomDateTimeProvider(datetime),
+        );
+
+        let wikitext = bot
+            .parsoid()
+            .transform_to_wikitext(&wikicode)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            &wikitext,
+            indoc! {r#"
+            {{BOTREQ|完了}}(ID: 01HCZ2CQPV5HW8NJAH6V1Z3KG9) 1件の操作を完了しました
+            
+            * [[:Category:Name1]]
+            ** [[:Category:Name2]]
+            *** [[:Category:Name3]]
+            ** [[:Category:Other]] - 名前に操作元のカテゴリ名を含まないため、所属ページを操作しませんでした
+            --[[User:QueueBot|QueueBot]]<small><span class="plainlinks">([[Special:Contributions/QueueBot|投稿]]/[{{fullurl:Special:Log/delete|user=QueueBot}} 削除]/[{{fullurl:Special:Log/move|user=QueueBot}} 移動])</span></small> 2023年10月17日 (火) 00:00 (UTC)"#}
+        );
+    }
+
+    #[tokio::test]
+    async fn test_format_message_with_disappeared() {
+        let bot = test::bot().await;
+
+        let datetime = Utc.with_ymd_and_hms(2023, 10, 17, 0, 0, 0).unwrap();
+
+        let wikicode = Wikicode::new("");
+        format_message(
+            &wikicode,
+            Some(&Ulid::from_string("01HCZ2CQPV5HW8NJAH6V1Z3KG9").unwrap()),
+            "完了",
+            "1件の操作を完了しました",
+            None,
+            Some(indexmap! {
+                "テスト".to_string() => Err("これはエラーです".to_string()),
+            }),
+            None,
+            Some(&["テスト2".to_string()]),
+            CustomDateTimeProvider(datetime),
+        );
+
+        let wikitext = bot
+            .parsoid()
+            .transform_to_wikitext(&wikicode)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            &wikitext,
+            indoc! {r#"
+            {{BOTREQ|完了}}(ID: 01HCZ2CQPV5HW8NJAH6V1Z3KG9) 1件の操作を完了しました
+            
+            * ダンプの時点では所属していたページ
+            ** [[テスト2]]
+            # [[テスト]] - これはエラーです
+            --[[User:QueueBot|QueueBot]]<small><span class="plainlinks">([[Special:Contributions/QueueBot|投稿]]/[{{fullurl:Special:Log/delete|user=QueueBot}} 削除]/[{{fullurl:Special:Log/move|user=QueueBot}} 移動])</span></small> 2023年10月17日 (火) 00:00 (UTC)"#}
+        );
+    }
+
+    #[test]
+    fn test_format_trial_preview() {
+        let id = Ulid::from_string("01HCZ2CQPV5HW8NJAH6V1Z3KG9").unwrap();
+        let preview = format_trial_preview(
+            &id,
+            &indexmap! {
+                "テスト".to_string() => Ok(OperationStatus::Previewed {
+                    diff: "-[[Category:Name1]]\n+[[Category:Name2]]".to_string(),
+                    changes: vec![ChangeRecord::new(
+                        "CategoryTagReplacer",
+                        "[[Category:Name1]] → [[Category:Name2]]",
+                    )],
+                }),
+                "テスト2".to_string() => Ok(OperationStatus::Skipped),
+                "テスト3".to_string() => Err("これはエラーです".to_string()),
+            },
+        );
+
+        assert_eq!(
+            preview,
+            indoc! {r#"
+            試行 (ID: 01HCZ2CQPV5HW8NJAH6V1Z3KG9) の結果、1件のページが変更されます。
+
+            == [[:テスト]] ==
+            * CategoryTagReplacer: <nowiki>[[Category:Name1]] → [[Category:Name2]]</nowiki>
+            <syntaxhighlight lang="diff">
+            -[[Category:Name1]]
+            +[[Category:Name2]]
+            </syntaxhighlight>
+
+            == [[:テスト3]] ==
+            エラー: これはエラーです
+            "#}
+            .trim_start()
+        );
+    }
+
+    #[test]
+    fn test_format_edit_request() {
+        let id = Ulid::from_string("01HCZ2CQPV5HW8NJAH6V1Z3KG9").unwrap();
+        let summary = "BOT: [[:Category:Name1]]から[[:Category:Name2]]へ変更 ([[議論|議論場所]]) (ID: 01HCZ2CQPV5HW8NJAH6V1Z3KG9)";
+
+        let request = format_edit_request(
+            &id,
+            summary,
+            &indexmap! {
+                "テスト".to_string() => Ok(OperationStatus::Done),
+                "テスト2".to_string() => Ok(OperationStatus::Protected),
+            },
+        );
+        assert_eq!(
+            request.as_deref(),
+            Some(indoc! {"
+            以下のページは保護されているため、QueueBotでは編集できませんでした (ID: 01HCZ2CQPV5HW8NJAH6V1Z3KG9)。
+            管理者の方は次の内容で編集をお願いします: [[:Category:Name1]]から[[:Category:Name2]]へ変更 ([[議論|議論場所]]) (ID: 01HCZ2CQPV5HW8NJAH6V1Z3KG9)
+
+            * [[:テスト2]]
+            "}.trim_start())
+        );
+
+        let request = format_edit_request(
+            &id,
+            summary,
+            &indexmap! { "テスト".to_string() => Ok(OperationStatus::Done) },
+        );
+        assert_eq!(request, None);
+    }
 }
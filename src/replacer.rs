@@ -1,19 +1,24 @@
-use std::fmt::Debug;
 use std::future::Future;
 
 use derivative::Derivative;
-use frunk_core::hlist;
 use frunk_core::hlist::{HCons, HNil};
 use mwbot::parsoid::prelude::*;
 use mwbot::Bot;
 
-use self::category_tag::CategoryTagReplacer;
-use self::recursion::RecursionReplacer;
-use self::template::category_of_redirects::CategoryOfRedirectsReplacer;
-use self::template::image_requested::ImageRequestedReplacer;
+pub use self::category_tag::TargetSortKeys;
+use self::change::short_type_name;
+pub use self::change::{describe_changes, summary_with_changes, ChangeRecord};
+use self::pipeline::ReplacerContext;
+pub use self::pipeline::{ReplacerPipeline, ReplacerRegistry};
+pub use self::sort_key::SortKeyRule;
+use crate::config::{ReplacerConfig, TemplateParameterRule};
+use crate::title::CategoryNormalizer;
 
 mod category_tag;
+mod change;
+mod pipeline;
 mod recursion;
+mod sort_key;
 mod template;
 
 pub trait CategoryReplacer: Send + Sync {
@@ -22,6 +27,40 @@ pub trait CategoryReplacer: Send + Sync {
         html: ImmutableWikicode,
     ) -> impl Future<Output = anyhow::Result<Option<ImmutableWikicode>>> + Send + Sync;
 
+    /// 変更の記録に使う名前
+    fn name(&self) -> &'static str {
+        short_type_name(std::any::type_name::<Self>())
+    }
+
+    /// `before` から `after` への置換で変更した内容
+    fn describe(&self, before: &ImmutableWikicode, after: &ImmutableWikicode) -> Vec<String> {
+        describe_changes(before, after)
+    }
+
+    /// 置換し、変更した内容を合わせて返す. 置換した場合は少なくとも1件の記録を返す
+    fn replace_with_changes(
+        &self,
+        html: ImmutableWikicode,
+    ) -> impl Future<Output = anyhow::Result<Option<(ImmutableWikicode, Vec<ChangeRecord>)>>> + Send + Sync
+    {
+        async move {
+            let Some(replaced) = self.replace(html.clone()).await? else {
+                return Ok(None);
+            };
+
+            let mut descriptions = self.describe(&html, &replaced);
+            if descriptions.is_empty() {
+                descriptions.push("書式を変更".to_string());
+            }
+            let changes = descriptions
+                .into_iter()
+                .map(|description| ChangeRecord::new(self.name(), description))
+                .collect();
+
+            Ok(Some((replaced, changes)))
+        }
+    }
+
     fn boxed(self) -> BoxedCategoryReplacer<Self>
     where
         Self: Sized,
@@ -40,6 +79,49 @@ where
             None => Ok(None),
         }
     }
+
+    async fn replace_with_changes(
+        &self,
+        html: ImmutableWikicode,
+    ) -> anyhow::Result<Option<(ImmutableWikicode, Vec<ChangeRecord>)>> {
+        match self {
+            Some(replacer) => replacer.replace_with_changes(html).await,
+            None => Ok(None),
+        }
+    }
+}
+
+/// 先頭から順に置換し、いずれかが置換した場合はその結果を返す
+impl<Replacer> CategoryReplacer for Vec<Replacer>
+where
+    Replacer: CategoryReplacer,
+{
+    async fn replace(&self, html: ImmutableWikicode) -> anyhow::Result<Option<ImmutableWikicode>> {
+        let mut replaced = None;
+        for replacer in self {
+            let current = replaced.clone().unwrap_or_else(|| html.clone());
+            if let Some(html) = replacer.replace(current).await? {
+                replaced = Some(html);
+            }
+        }
+        Ok(replaced)
+    }
+
+    async fn replace_with_changes(
+        &self,
+        html: ImmutableWikicode,
+    ) -> anyhow::Result<Option<(ImmutableWikicode, Vec<ChangeRecord>)>> {
+        let mut replaced = None;
+        let mut changes = vec![];
+        for replacer in self {
+            let current = replaced.clone().unwrap_or_else(|| html.clone());
+            if let Some((html, records)) = replacer.replace_with_changes(current).await? {
+                replaced = Some(html);
+                changes.extend(records);
+            }
+        }
+        Ok(replaced.map(|replaced| (replaced, changes)))
+    }
 }
 
 #[derive(Derivative)]
@@ -72,23 +154,43 @@ where
     ) -> impl Future<Output = anyhow::Result<Option<ImmutableWikicode>>> + Send + Sync {
         Box::pin(self.inner.replace(html))
     }
+
+    fn replace_with_changes(
+        &self,
+        html: ImmutableWikicode,
+    ) -> impl Future<Output = anyhow::Result<Option<(ImmutableWikicode, Vec<ChangeRecord>)>>> + Send + Sync
+    {
+        Box::pin(self.inner.replace_with_changes(html))
+    }
 }
 
 pub trait CategoryReplacerList: Send + Sync {
-    /// If the result of the substitution is the same as the original ImmutableWikicode,
-    /// the bool in the return tuple returns false
+    /// 置換した結果と、各置換処理の変更の記録を返す.
+    /// 元のImmutableWikicodeから変わらなかった場合、記録は空になる
     fn replace_all(
         &self,
         html: ImmutableWikicode,
-    ) -> impl Future<Output = anyhow::Result<(ImmutableWikicode, bool)>> + Send + Sync;
+    ) -> impl Future<Output = anyhow::Result<(ImmutableWikicode, Vec<ChangeRecord>)>> + Send + Sync;
+
+    /// 名前空間IDが `namespace` のページを置換する.
+    /// 名前空間によって置換処理を変えない場合は [`Self::replace_all`] と同じ
+    fn replace_all_in(
+        &self,
+        namespace: i32,
+        html: ImmutableWikicode,
+    ) -> impl Future<Output = anyhow::Result<(ImmutableWikicode, Vec<ChangeRecord>)>> + Send + Sync
+    {
+        let _ = namespace;
+        self.replace_all(html)
+    }
 }
 
 impl CategoryReplacerList for HNil {
     async fn replace_all(
         &self,
         html: ImmutableWikicode,
-    ) -> anyhow::Result<(ImmutableWikicode, bool)> {
-        Ok((html, false))
+    ) -> anyhow::Result<(ImmutableWikicode, Vec<ChangeRecord>)> {
+        Ok((html, vec![]))
     }
 }
 
@@ -100,27 +202,66 @@ where
     async fn replace_all(
         &self,
         html: ImmutableWikicode,
-    ) -> anyhow::Result<(ImmutableWikicode, bool)> {
-        let replaced = self.head.replace(html.clone()).await?;
-        let head_is_changed = replaced.is_some();
-        let (tail_replaced, tail_is_changed) =
-            self.tail.replace_all(replaced.unwrap_or(html)).await?;
+    ) -> anyhow::Result<(ImmutableWikicode, Vec<ChangeRecord>)> {
+        let (html, mut changes) = match self.head.replace_with_changes(html.clone()).await? {
+            Some((replaced, changes)) => (replaced, changes),
+            None => (html, vec![]),
+        };
+        let (tail_replaced, tail_changes) = self.tail.replace_all(html).await?;
+        changes.extend(tail_changes);
 
-        Ok((tail_replaced, head_is_changed || tail_is_changed))
+        Ok((tail_replaced, changes))
     }
 }
 
+pub type CategoryReplacers = ReplacerPipeline;
+
+/// コマンドごとに変わる置換の設定
+#[derive(Debug, Clone, Default)]
+pub struct ReplacerOptions {
+    /// 付け替え先のカテゴリタグのソートキー
+    pub sort_keys: TargetSortKeys,
+    /// テンプレートの引数の置換規則
+    pub template_rules: Vec<TemplateParameterRule>,
+    /// 使う置換処理の名前
+    pub replacers: ReplacerConfig,
+}
+
+/// `from` のカテゴリを `to` のカテゴリへ付け替えるReplacer
 pub fn get_category_replacers(
     bot: Bot,
     from: String,
     to: Vec<String>,
-) -> impl CategoryReplacerList + Debug {
-    hlist![RecursionReplacer::new(
-        bot,
-        hlist![
-            CategoryTagReplacer::new(from.clone(), to.clone()),
-            CategoryOfRedirectsReplacer::new(from.clone(), to.clone()),
-            ImageRequestedReplacer::new(from, to),
-        ],
-    )]
+    options: ReplacerOptions,
+) -> CategoryReplacers {
+    build_replacers(bot, from, to, options, None)
+}
+
+/// `category` のカテゴリタグのソートキーを `rule` に従って変更するReplacer
+pub fn get_sort_key_replacers(
+    bot: Bot,
+    category: String,
+    rule: SortKeyRule,
+    options: ReplacerOptions,
+) -> CategoryReplacers {
+    build_replacers(bot, category, vec![], options, Some(rule))
+}
+
+fn build_replacers(
+    bot: Bot,
+    from: String,
+    to: Vec<String>,
+    options: ReplacerOptions,
+    sort_key_rule: Option<SortKeyRule>,
+) -> CategoryReplacers {
+    let context = ReplacerContext {
+        from,
+        to,
+        sort_keys: options.sort_keys,
+        template_rules: options.template_rules,
+        sort_key_rule,
+        normalizer: CategoryNormalizer::new(&bot),
+    };
+
+    ReplacerRegistry::default().build(bot, &context, &options.replacers)
 }
@@ -1,6 +1,36 @@
+use std::collections::HashMap;
+
 use mwbot::parsoid::prelude::*;
 
 use crate::replacer::CategoryReplacer;
+use crate::title::CategoryNormalizer;
+
+/// 付け替え先のカテゴリタグに指定するソートキー
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TargetSortKeys {
+    /// 付け替え先ごとに指定されたソートキー
+    keys: HashMap<String, String>,
+    /// 指定されていない付け替え先にも元のソートキーを引き継ぐ
+    keep_original: bool,
+}
+
+impl TargetSortKeys {
+    pub fn new(keys: HashMap<String, String>, keep_original: bool) -> Self {
+        Self {
+            keys,
+            keep_original,
+        }
+    }
+
+    /// `to` のカテゴリタグのソートキー. 指定がない場合、付け替え元と同じカテゴリには元のソートキーを引き継ぐ
+    fn sort_key(&self, to: &str, original: Option<&str>, is_source: bool) -> Option<String> {
+        match self.keys.get(to) {
+            Some(key) => Some(key.clone()),
+            None if is_source || self.keep_original => original.map(ToString::to_string),
+            None => None,
+        }
+    }
+}
 
 /// カテゴリタグ(`[[Category:Example]]`)の置換
 /// `to` が空の場合、`from` のカテゴリを削除する
@@ -8,11 +38,27 @@ use crate::replacer::CategoryReplacer;
 pub struct CategoryTagReplacer {
     from: String,
     to: Vec<String>,
+    sort_keys: TargetSortKeys,
+    normalizer: CategoryNormalizer,
 }
 
 impl CategoryTagReplacer {
-    pub fn new(from: String, to: Vec<String>) -> Self {
-        Self { from, to }
+    pub fn new(
+        from: String,
+        to: Vec<String>,
+        sort_keys: TargetSortKeys,
+        normalizer: CategoryNormalizer,
+    ) -> Self {
+        Self {
+            from,
+            to,
+            sort_keys,
+            normalizer,
+        }
+    }
+
+    fn is_same(&self, a: &str, b: &str) -> bool {
+        self.normalizer.is_same(a, b)
     }
 }
 
@@ -21,18 +67,19 @@ impl CategoryReplacer for CategoryTagReplacer {
         let html = html.into_mutable();
         let mut categories = html.filter_categories();
 
-        if self.to.contains(&self.from)
-            && self
-                .to
-                .iter()
-                .all(|to| categories.iter().any(|cat| cat.category() == *to))
+        if self.to.iter().any(|to| self.is_same(to, &self.from))
+            && self.to.iter().all(|to| {
+                categories
+                    .iter()
+                    .any(|cat| self.is_same(&cat.category(), to))
+            })
         {
             return Ok(None);
         }
 
         let Some(index) = categories
             .iter()
-            .position(|category| category.category() == self.from)
+            .position(|category| self.is_same(&category.category(), &self.from))
         else {
             return Ok(None);
         };
@@ -40,14 +87,19 @@ impl CategoryReplacer for CategoryTagReplacer {
 
         self.to
             .iter()
-            .filter(|to| !categories.iter().any(|cat| cat.category() == **to))
+            .filter(|to| {
+                !categories
+                    .iter()
+                    .any(|cat| self.is_same(&cat.category(), to))
+            })
             .for_each(|cat| {
                 dbg!(&cat);
-                if *cat == from.category() {
-                    from.insert_before(&Category::new(cat, from.sort_key().as_deref()));
-                } else {
-                    from.insert_before(&Category::new(cat, None));
-                }
+                let sort_key = self.sort_keys.sort_key(
+                    cat,
+                    from.sort_key().as_deref(),
+                    self.is_same(cat, &from.category()),
+                );
+                from.insert_before(&Category::new(cat, sort_key.as_deref()));
             });
         from.detach();
         dbg!(html
@@ -62,12 +114,15 @@ impl CategoryReplacer for CategoryTagReplacer {
 
 #[cfg(test)]
 mod test {
+    use std::collections::HashMap;
+
     use indoc::indoc;
     use pretty_assertions::assert_str_eq;
     use rstest::rstest;
 
-    use crate::replacer::category_tag::CategoryTagReplacer;
+    use crate::replacer::category_tag::{CategoryTagReplacer, TargetSortKeys};
     use crate::replacer::CategoryReplacer;
+    use crate::title::CategoryNormalizer;
     use crate::util::test;
 
     #[rstest(::trace)]
@@ -162,7 +217,12 @@ mod test {
 
         let html = bot.parsoid().transform_to_html(before_wikitext).await?;
 
-        let replacer = CategoryTagReplacer::new(from.to_string(), to);
+        let replacer = CategoryTagReplacer::new(
+            from.to_string(),
+            to,
+            TargetSortKeys::default(),
+            CategoryNormalizer::new(&bot),
+        );
         let replaced = replacer.replace(html).await?;
 
         if should_be_changed {
@@ -178,4 +238,98 @@ mod test {
 
         Ok(())
     }
+
+    #[rstest]
+    #[case(
+        TargetSortKeys::default(),
+        &["Category:Name2", "Category:Name3"],
+        indoc! {"\
+            [[Category:Name2]]
+            [[Category:Name3]]
+        "},
+    )]
+    #[case(
+        TargetSortKeys::new(HashMap::new(), true),
+        &["Category:Name2", "Category:Name3"],
+        indoc! {"\
+            [[Category:Name2|きい]]
+            [[Category:Name3|きい]]
+        "},
+    )]
+    #[case(
+        TargetSortKeys::new(
+            HashMap::from([("Category:Name3".to_string(), "べつ".to_string())]),
+            false,
+        ),
+        &["Category:Name2", "Category:Name3"],
+        indoc! {"\
+            [[Category:Name2]]
+            [[Category:Name3|べつ]]
+        "},
+    )]
+    #[case(
+        TargetSortKeys::new(
+            HashMap::from([("Category:Name3".to_string(), "べつ".to_string())]),
+            true,
+        ),
+        &["Category:Name1", "Category:Name3"],
+        indoc! {"\
+            [[Category:Name1|きい]]
+            [[Category:Name3|べつ]]
+        "},
+    )]
+    #[tokio::test]
+    async fn test_replace_with_sort_keys(
+        #[case] sort_keys: TargetSortKeys,
+        #[case] to: &[&str],
+        #[case] after_wikitext: &str,
+    ) -> anyhow::Result<()> {
+        let bot = test::bot().await;
+        let to = to.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+
+        let html = bot
+            .parsoid()
+            .transform_to_html("[[Category:Name1|きい]]\n")
+            .await?;
+
+        let replacer = CategoryTagReplacer::new(
+            "Category:Name1".to_string(),
+            to,
+            sort_keys,
+            CategoryNormalizer::new(&bot),
+        );
+        let replaced = replacer.replace(html).await?;
+
+        let replaced_wikitext = bot
+            .parsoid()
+            .transform_to_wikitext(&replaced.expect("wikitext should be changed"))
+            .await?;
+        assert_str_eq!(after_wikitext, replaced_wikitext);
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case("Category:Name2", Some("きい"), false, false, None)]
+    #[case("Category:Name2", Some("きい"), true, false, Some("きい"))]
+    #[case("Category:Name2", Some("きい"), false, true, Some("きい"))]
+    #[case("Category:Name3", Some("きい"), false, false, Some("べつ"))]
+    #[case("Category:Name3", None, true, true, Some("べつ"))]
+    #[case("Category:Name2", None, true, true, None)]
+    fn test_target_sort_key(
+        #[case] to: &str,
+        #[case] original: Option<&str>,
+        #[case] is_source: bool,
+        #[case] keep_original: bool,
+        #[case] expected: Option<&str>,
+    ) {
+        let sort_keys = TargetSortKeys::new(
+            HashMap::from([("Category:Name3".to_string(), "べつ".to_string())]),
+            keep_original,
+        );
+        assert_eq!(
+            sort_keys.sort_key(to, original, is_source).as_deref(),
+            expected
+        );
+    }
 }
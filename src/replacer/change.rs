@@ -0,0 +1,295 @@
+use std::fmt::{self, Display};
+
+use indexmap::IndexMap;
+use mwbot::parsoid::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// 置換処理がページに加えた変更. 操作とともに記録し、編集要約や報告に使う
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChangeRecord {
+    /// 変更した置換処理の名前
+    pub replacer: String,
+    /// 変更内容. 例えば `[[Category:A|x]] → [[Category:B|x]]`
+    pub description: String,
+}
+
+impl ChangeRecord {
+    pub fn new(replacer: impl Into<String>, description: impl Into<String>) -> Self {
+        Self {
+            replacer: replacer.into(),
+            description: description.into(),
+        }
+    }
+}
+
+impl Display for ChangeRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.replacer, self.description)
+    }
+}
+
+/// 編集要約の最大文字数
+const SUMMARY_MAX_CHARS: usize = 500;
+
+/// 編集要約に変更内容を付け加える. 収まらない変更は件数のみ記載する
+pub fn summary_with_changes(summary: &str, changes: &[ChangeRecord]) -> String {
+    if changes.is_empty() {
+        return summary.to_string();
+    }
+
+    for shown in (0..=changes.len()).rev() {
+        let mut details = changes[..shown]
+            .iter()
+            .map(|change| change.description.as_str())
+            .collect::<Vec<_>>()
+            .join("; ");
+        let rest = changes.len() - shown;
+        if rest > 0 {
+            if !details.is_empty() {
+                details.push_str("; ");
+            }
+            details.push_str(&format!("ほか{rest}件の変更"));
+        }
+
+        let result = format!("{summary} ({details})");
+        if result.chars().count() <= SUMMARY_MAX_CHARS {
+            return result;
+        }
+    }
+
+    summary.to_string()
+}
+
+/// 型名からモジュールのパスと型引数を除いた名前
+pub(crate) fn short_type_name(name: &'static str) -> &'static str {
+    let name = name.split('<').next().unwrap_or(name);
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+/// `before` から `after` へのカテゴリタグとテンプレートの引数の変更
+pub fn describe_changes(before: &ImmutableWikicode, after: &ImmutableWikicode) -> Vec<String> {
+    let before = before.clone().into_mutable();
+    let after = after.clone().into_mutable();
+
+    let mut changes = describe_category_tags(&before, &after);
+    changes.extend(describe_template_params(&before, &after));
+    changes
+}
+
+/// カテゴリタグの変更. 外したタグと付けたタグを `→` でつなぐ
+fn describe_category_tags(before: &Wikicode, after: &Wikicode) -> Vec<String> {
+    let mut removed = category_tags(before);
+    let mut added = category_tags(after);
+    let unchanged = removed
+        .iter()
+        .filter(|tag| added.contains(tag))
+        .cloned()
+        .collect::<Vec<_>>();
+    for tag in unchanged {
+        removed.retain(|removed| removed != &tag);
+        added.retain(|added| added != &tag);
+    }
+
+    match (removed.is_empty(), added.is_empty()) {
+        (true, true) => vec![],
+        (false, true) => vec![format!("{} を削除", removed.join("、"))],
+        (true, false) => vec![format!("{} を追加", added.join("、"))],
+        (false, false) => vec![format!("{} → {}", removed.join("、"), added.join("、"))],
+    }
+}
+
+fn category_tags(html: &Wikicode) -> Vec<String> {
+    html.filter_categories()
+        .iter()
+        .map(|category| match category.sort_key() {
+            Some(sort_key) => format!("[[{}|{}]]", category.category(), sort_key),
+            None => format!("[[{}]]", category.category()),
+        })
+        .collect()
+}
+
+/// テンプレートの引数の変更. 同じ名前のテンプレートを出現順に対応させて比べる
+fn describe_template_params(before: &Wikicode, after: &Wikicode) -> Vec<String> {
+    let mut before = templates_by_name(before);
+    let mut after = templates_by_name(after);
+
+    let mut names = before.keys().cloned().collect::<Vec<_>>();
+    names.extend(
+        after
+            .keys()
+            .filter(|name| !before.contains_key(*name))
+            .cloned(),
+    );
+
+    let mut changes = vec![];
+    for name in names {
+        let before = before.shift_remove(&name).unwrap_or_default();
+        let after = after.shift_remove(&name).unwrap_or_default();
+        let display = format!("{{{{{}}}}}", name.trim_start_matches("Template:"));
+
+        for i in 0..before.len().max(after.len()) {
+            match (before.get(i), after.get(i)) {
+                (Some(before), Some(after)) => {
+                    changes.extend(
+                        describe_params(before, after)
+                            .into_iter()
+                            .map(|change| format!("{display}: {change}")),
+                    );
+                }
+                (Some(_), None) => changes.push(format!("{display} を削除")),
+                (None, Some(_)) => changes.push(format!("{display} を追加")),
+                (None, None) => {}
+            }
+        }
+    }
+
+    changes
+}
+
+fn templates_by_name(html: &Wikicode) -> IndexMap<String, Vec<IndexMap<String, String>>> {
+    let mut templates = IndexMap::<_, Vec<_>>::new();
+    for template in html.filter_templates().unwrap_or_default() {
+        templates
+            .entry(template.name())
+            .or_default()
+            .push(template.params());
+    }
+    templates
+}
+
+fn describe_params(
+    before: &IndexMap<String, String>,
+    after: &IndexMap<String, String>,
+) -> Vec<String> {
+    let mut changes = before
+        .iter()
+        .filter_map(|(key, value)| match after.get(key) {
+            Some(new_value) if new_value == value => None,
+            Some(new_value) => Some(format!("{key} = {value} → {new_value}")),
+            None => Some(format!("{key} = {value} を削除")),
+        })
+        .collect::<Vec<_>>();
+    changes.extend(
+        after
+            .iter()
+            .filter(|(key, _)| !before.contains_key(*key))
+            .map(|(key, value)| format!("{key} = {value} を追加")),
+    );
+    changes
+}
+
+#[cfg(test)]
+mod test {
+    use indexmap::indexmap;
+    use rstest::rstest;
+
+    use super::*;
+    use crate::util::test;
+
+    #[rstest]
+    #[case(
+        "queuebot::replacer::category_tag::CategoryTagReplacer",
+        "CategoryTagReplacer"
+    )]
+    #[case(
+        "queuebot::replacer::recursion::RecursionReplacer<queuebot::replacer::pipeline::ReplacerPipeline>",
+        "RecursionReplacer"
+    )]
+    #[case("FixedReplacer", "FixedReplacer")]
+    fn test_short_type_name(#[case] name: &'static str, #[case] expected: &str) {
+        assert_eq!(short_type_name(name), expected);
+    }
+
+    #[rstest]
+    #[case(
+        "[[Category:A|x]]\n[[Category:C]]\n",
+        "[[Category:B|x]]\n[[Category:C]]\n",
+        &["[[Category:A|x]] → [[Category:B|x]]"],
+    )]
+    #[case("[[Category:A]]\n", "", &["[[Category:A]] を削除"])]
+    #[case(
+        "{{リダイレクトの所属カテゴリ|redirect1=旧名|1-1=Category:A}}\n",
+        "{{リダイレクトの所属カテゴリ|redirect1=旧名|1-1=Category:B}}\n",
+        &["{{リダイレクトの所属カテゴリ}}: 1-1 = Category:A → Category:B"],
+    )]
+    #[case(
+        "{{リダイレクトの所属カテゴリ|Category:A}}\n",
+        "",
+        &["{{リダイレクトの所属カテゴリ}} を削除"],
+    )]
+    #[tokio::test]
+    async fn test_describe_changes(
+        #[case] before: &str,
+        #[case] after: &str,
+        #[case] expected: &[&str],
+    ) -> anyhow::Result<()> {
+        let bot = test::bot().await;
+        let before = bot.parsoid().transform_to_html(before).await?;
+        let after = bot.parsoid().transform_to_html(after).await?;
+
+        assert_eq!(describe_changes(&before, &after), expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_describe_params() {
+        let before = indexmap! {
+            "redirect1".to_string() => "旧名".to_string(),
+            "1-1".to_string() => "Category:A".to_string(),
+            "1-2".to_string() => "Category:B".to_string(),
+        };
+        let after = indexmap! {
+            "redirect1".to_string() => "旧名".to_string(),
+            "1-1".to_string() => "Category:C".to_string(),
+            "1-3".to_string() => "Category:D".to_string(),
+        };
+
+        assert_eq!(
+            describe_params(&before, &after),
+            [
+                "1-1 = Category:A → Category:C",
+                "1-2 = Category:B を削除",
+                "1-3 = Category:D を追加",
+            ]
+        );
+    }
+
+    #[rstest]
+    #[case(0, "BOT: カテゴリの変更")]
+    #[case(1, "BOT: カテゴリの変更 ([[Category:A]] → [[Category:B]])")]
+    #[case(
+        2,
+        "BOT: カテゴリの変更 ([[Category:A]] → [[Category:B]]; [[Category:A]] → [[Category:B]])"
+    )]
+    fn test_summary_with_changes(#[case] count: usize, #[case] expected: &str) {
+        let changes =
+            vec![
+                ChangeRecord::new("CategoryTagReplacer", "[[Category:A]] → [[Category:B]]");
+                count
+            ];
+        assert_eq!(
+            summary_with_changes("BOT: カテゴリの変更", &changes),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_summary_with_too_many_changes() {
+        let changes = vec![ChangeRecord::new("CategoryTagReplacer", "あ".repeat(200)); 3];
+        let summary = summary_with_changes("BOT: カテゴリの変更", &changes);
+
+        assert!(summary.chars().count() <= SUMMARY_MAX_CHARS);
+        assert!(summary.ends_with("; ほか1件の変更)"));
+    }
+
+    #[test]
+    fn test_change_record_display() {
+        let record =
+            ChangeRecord::new("CategoryTagReplacer", "[[Category:A|x]] → [[Category:B|x]]");
+        assert_eq!(
+            record.to_string(),
+            "CategoryTagReplacer: [[Category:A|x]] → [[Category:B|x]]"
+        );
+    }
+}
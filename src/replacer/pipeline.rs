@@ -0,0 +1,414 @@
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use indexmap::IndexMap;
+use mwbot::parsoid::prelude::*;
+use mwbot::Bot;
+use tracing::{info, warn};
+
+use crate::config::{ReplacerConfig, TemplateParameterRule};
+use crate::replacer::category_tag::{CategoryTagReplacer, TargetSortKeys};
+use crate::replacer::recursion::RecursionReplacer;
+use crate::replacer::sort_key::{SortKeyReplacer, SortKeyRule};
+use crate::replacer::template::category_of_redirects::CategoryOfRedirectsReplacer;
+use crate::replacer::template::image_requested::ImageRequestedReplacer;
+use crate::replacer::template::template_parameter::TemplateParameterReplacer;
+use crate::replacer::{CategoryReplacer, CategoryReplacerList, ChangeRecord};
+use crate::title::CategoryNormalizer;
+
+/// テンプレートの引数の中も置換する処理の名前. 他の置換処理を包むため、レジストリには登録しない.
+/// 設定のどの位置に記載しても、他の置換処理を包んで最初に実行する
+pub const RECURSION: &str = "recursion";
+
+type ReplaceFuture<'a> = Pin<
+    Box<
+        dyn Future<Output = anyhow::Result<Option<(ImmutableWikicode, Vec<ChangeRecord>)>>>
+            + Send
+            + Sync
+            + 'a,
+    >,
+>;
+
+/// 実行時に組み合わせるための [`CategoryReplacer`]
+pub trait DynCategoryReplacer: Send + Sync {
+    fn replace_dyn(&self, html: ImmutableWikicode) -> ReplaceFuture<'_>;
+}
+
+impl<Replacer> DynCategoryReplacer for Replacer
+where
+    Replacer: CategoryReplacer,
+{
+    fn replace_dyn(&self, html: ImmutableWikicode) -> ReplaceFuture<'_> {
+        Box::pin(self.replace_with_changes(html))
+    }
+}
+
+/// 置換処理を作るのに必要な、コマンドの内容
+#[derive(Debug, Clone)]
+pub struct ReplacerContext {
+    pub from: String,
+    pub to: Vec<String>,
+    pub sort_keys: TargetSortKeys,
+    pub template_rules: Vec<TemplateParameterRule>,
+    /// ソートキーの変更時はカテゴリの付け替えを行わない
+    pub sort_key_rule: Option<SortKeyRule>,
+    pub normalizer: CategoryNormalizer,
+}
+
+impl ReplacerContext {
+    fn reassign(&self) -> bool {
+        self.sort_key_rule.is_none()
+    }
+}
+
+/// コマンドの内容から置換処理を作る. 対象外のコマンドの場合は `None` を返す
+pub type ReplacerFactory = fn(&ReplacerContext) -> Option<Arc<dyn DynCategoryReplacer>>;
+
+/// 名前付きの置換処理. 設定に記載された名前の順に組み立てる
+pub struct ReplacerRegistry {
+    factories: IndexMap<&'static str, ReplacerFactory>,
+}
+
+impl Default for ReplacerRegistry {
+    fn default() -> Self {
+        let mut registry = Self {
+            factories: IndexMap::new(),
+        };
+        registry.register("category_tag", |context| {
+            context.reassign().then(|| {
+                Arc::new(CategoryTagReplacer::new(
+                    context.from.clone(),
+                    context.to.clone(),
+                    context.sort_keys.clone(),
+                    context.normalizer.clone(),
+                )) as _
+            })
+        });
+        registry.register("category_of_redirects", |context| {
+            context.reassign().then(|| {
+                Arc::new(CategoryOfRedirectsReplacer::new(
+                    context.from.clone(),
+                    context.to.clone(),
+                    context.normalizer.clone(),
+                )) as _
+            })
+        });
+        registry.register("image_requested", |context| {
+            ImageRequestedReplacer::new(
+                context.from.clone(),
+                context.to.clone(),
+                context.normalizer.clone(),
+            )
+            .filter(|_| context.reassign())
+            .map(|replacer| Arc::new(replacer) as _)
+        });
+        registry.register("template_parameter", |context| {
+            let replacers = context
+                .template_rules
+                .iter()
+                .filter(|_| context.reassign())
+                .filter_map(|rule| {
+                    TemplateParameterReplacer::new(
+                        context.from.clone(),
+                        context.to.clone(),
+                        rule.clone(),
+                        context.normalizer.clone(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            (!replacers.is_empty()).then(|| Arc::new(replacers) as _)
+        });
+        registry.register("sort_key", |context| {
+            context.sort_key_rule.clone().map(|rule| {
+                Arc::new(SortKeyReplacer::new(
+                    context.from.clone(),
+                    rule,
+                    context.normalizer.clone(),
+                )) as _
+            })
+        });
+        registry
+    }
+}
+
+impl ReplacerRegistry {
+    /// 同じ名前で登録されている場合は置き換える
+    pub fn register(&mut self, name: &'static str, factory: ReplacerFactory) {
+        self.factories.insert(name, factory);
+    }
+
+    /// コマンドで有効・無効を指定できる名前か
+    pub fn contains(&self, name: &str) -> bool {
+        name == RECURSION || self.factories.contains_key(name)
+    }
+
+    /// `config` で有効にされた置換処理を、名前空間ごとに組み立てる
+    pub fn build(
+        &self,
+        bot: Bot,
+        context: &ReplacerContext,
+        config: &ReplacerConfig,
+    ) -> ReplacerPipeline {
+        let mut pipeline = self.build_steps(bot.clone(), context, &config.enabled);
+        for namespace in &config.namespaces {
+            let steps = self.build_steps(bot.clone(), context, &namespace.enabled);
+            for id in &namespace.namespaces {
+                pipeline.namespaces.insert(*id, steps.clone());
+            }
+        }
+        pipeline
+    }
+
+    /// `names` の置換処理を順に実行する列を組み立てる. 登録されていない名前は無視する
+    fn build_steps(
+        &self,
+        bot: Bot,
+        context: &ReplacerContext,
+        names: &[String],
+    ) -> ReplacerPipeline {
+        let steps = names
+            .iter()
+            .filter(|name| *name != RECURSION)
+            .filter_map(|name| match self.factories.get_key_value(name.as_str()) {
+                Some((name, factory)) => Some((*name, factory(context)?)),
+                None => {
+                    warn!(replacer = name, "Ignoring unknown replacer in config");
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        let pipeline = ReplacerPipeline {
+            steps,
+            namespaces: HashMap::new(),
+        };
+
+        if names.iter().any(|name| name == RECURSION) {
+            ReplacerPipeline {
+                steps: vec![(RECURSION, Arc::new(RecursionReplacer::new(bot, pipeline)))],
+                namespaces: HashMap::new(),
+            }
+        } else {
+            pipeline
+        }
+    }
+}
+
+/// 実行時に組み立てた置換処理の列. 置換した処理と、その変更内容をログに記録する
+#[derive(Clone, Default)]
+pub struct ReplacerPipeline {
+    steps: Vec<(&'static str, Arc<dyn DynCategoryReplacer>)>,
+    /// 名前空間ごとに異なる置換処理の列. ない名前空間には `steps` を使う
+    namespaces: HashMap<u32, ReplacerPipeline>,
+}
+
+impl ReplacerPipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(mut self, name: &'static str, replacer: impl CategoryReplacer + 'static) -> Self {
+        self.steps.push((name, Arc::new(replacer)));
+        self
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.steps.iter().map(|(name, _)| *name).collect()
+    }
+
+    /// 名前空間IDが `namespace` のページに使う置換処理の列
+    pub fn for_namespace(&self, namespace: i32) -> &Self {
+        u32::try_from(namespace)
+            .ok()
+            .and_then(|namespace| self.namespaces.get(&namespace))
+            .unwrap_or(self)
+    }
+}
+
+impl Debug for ReplacerPipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReplacerPipeline")
+            .field("steps", &self.names())
+            .field("namespaces", &self.namespaces)
+            .finish()
+    }
+}
+
+impl CategoryReplacerList for ReplacerPipeline {
+    async fn replace_all(
+        &self,
+        html: ImmutableWikicode,
+    ) -> anyhow::Result<(ImmutableWikicode, Vec<ChangeRecord>)> {
+        let mut html = html;
+        let mut changes = vec![];
+        for (name, replacer) in &self.steps {
+            let Some((replaced, mut records)) = replacer.replace_dyn(html.clone()).await? else {
+                continue;
+            };
+
+            for record in &mut records {
+                // 設定と対応付けられるよう、レジストリの名前で記録する.
+                // テンプレートの引数の中の変更は、包んでいる置換処理の名前で記録済み
+                if *name != RECURSION {
+                    record.replacer = name.to_string();
+                }
+                info!(replacer = name, change = %record, "Replaced");
+            }
+
+            html = replaced;
+            changes.extend(records);
+        }
+
+        Ok((html, changes))
+    }
+
+    async fn replace_all_in(
+        &self,
+        namespace: i32,
+        html: ImmutableWikicode,
+    ) -> anyhow::Result<(ImmutableWikicode, Vec<ChangeRecord>)> {
+        self.for_namespace(namespace).replace_all(html).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mwbot::parsoid::prelude::*;
+
+    use crate::config::{NamespaceReplacers, ReplacerConfig};
+    use crate::replacer::pipeline::{
+        ReplacerContext,
+        ReplacerPipeline,
+        ReplacerRegistry,
+        RECURSION,
+    };
+    use crate::replacer::{CategoryReplacer, CategoryReplacerList, ChangeRecord, TargetSortKeys};
+    use crate::title::CategoryNormalizer;
+    use crate::util::test;
+
+    /// 本文を `html` に置き換える
+    struct FixedReplacer {
+        html: Option<&'static str>,
+    }
+
+    impl CategoryReplacer for FixedReplacer {
+        async fn replace(
+            &self,
+            _html: ImmutableWikicode,
+        ) -> anyhow::Result<Option<ImmutableWikicode>> {
+            Ok(self.html.map(ImmutableWikicode::new))
+        }
+
+        fn describe(&self, _before: &ImmutableWikicode, after: &ImmutableWikicode) -> Vec<String> {
+            vec![after.clone().into_mutable().text_contents()]
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replace_all() -> anyhow::Result<()> {
+        let pipeline = ReplacerPipeline::new()
+            .push(
+                "first",
+                FixedReplacer {
+                    html: Some("<p>first</p>"),
+                },
+            )
+            .push("unchanged", FixedReplacer { html: None })
+            .push(
+                "last",
+                FixedReplacer {
+                    html: Some("<p>last</p>"),
+                },
+            );
+        assert_eq!(pipeline.names(), ["first", "unchanged", "last"]);
+
+        let (html, changes) = pipeline
+            .replace_all(ImmutableWikicode::new("<p>before</p>"))
+            .await?;
+        assert_eq!(
+            changes,
+            [
+                ChangeRecord::new("first", "first"),
+                ChangeRecord::new("last", "last"),
+            ]
+        );
+        assert_eq!(html.into_mutable().text_contents(), "last");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replace_all_unchanged() -> anyhow::Result<()> {
+        let pipeline = ReplacerPipeline::new().push("unchanged", FixedReplacer { html: None });
+
+        let (html, changes) = pipeline
+            .replace_all(ImmutableWikicode::new("<p>before</p>"))
+            .await?;
+        assert!(changes.is_empty());
+        assert_eq!(html.into_mutable().text_contents(), "before");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_registry_contains() {
+        let registry = ReplacerRegistry::default();
+        assert!(registry.contains("category_tag"));
+        assert!(registry.contains(RECURSION));
+        assert!(!registry.contains("unknown"));
+    }
+
+    #[tokio::test]
+    async fn test_build_per_namespace() {
+        let bot = test::bot().await;
+        let context = ReplacerContext {
+            from: "Category:Name1".to_string(),
+            to: vec!["Category:Name2".to_string()],
+            sort_keys: TargetSortKeys::default(),
+            template_rules: vec![],
+            sort_key_rule: None,
+            normalizer: CategoryNormalizer::new(&bot),
+        };
+        let config = ReplacerConfig {
+            enabled: vec![
+                "category_of_redirects".to_string(),
+                "category_tag".to_string(),
+            ],
+            namespaces: vec![NamespaceReplacers {
+                namespaces: vec![6],
+                enabled: vec![RECURSION.to_string(), "category_tag".to_string()],
+            }],
+        };
+
+        let pipeline = ReplacerRegistry::default().build(bot, &context, &config);
+
+        // 記載した順に実行する
+        assert_eq!(pipeline.names(), ["category_of_redirects", "category_tag"]);
+        assert_eq!(pipeline.for_namespace(0).names(), pipeline.names());
+        assert_eq!(pipeline.for_namespace(6).names(), [RECURSION]);
+    }
+
+    #[test]
+    fn test_config_switched() {
+        let config = ReplacerConfig {
+            enabled: vec![RECURSION.to_string(), "category_tag".to_string()],
+            namespaces: vec![NamespaceReplacers {
+                namespaces: vec![6],
+                enabled: vec!["category_tag".to_string(), "image_requested".to_string()],
+            }],
+        };
+
+        let switched = config.switched(&[
+            (RECURSION.to_string(), false),
+            ("image_requested".to_string(), true),
+        ]);
+
+        assert_eq!(switched.enabled, ["category_tag", "image_requested"]);
+        assert_eq!(
+            switched.namespaces[0].enabled,
+            ["category_tag", "image_requested"]
+        );
+    }
+}
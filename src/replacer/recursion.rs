@@ -5,7 +5,7 @@ use mwbot::parsoid::prelude::*;
 use mwbot::Bot;
 use tap::Pipe;
 
-use crate::replacer::{CategoryReplacer, CategoryReplacerList};
+use crate::replacer::{CategoryReplacer, CategoryReplacerList, ChangeRecord};
 
 #[derive(Derivative)]
 #[derivative(Debug, Clone)]
@@ -30,7 +30,18 @@ where
     ReplacerList: CategoryReplacerList + Clone,
 {
     async fn replace(&self, html: ImmutableWikicode) -> anyhow::Result<Option<ImmutableWikicode>> {
-        let (replaced, mut is_changed) = self.replacers.replace_all(html).await?;
+        Ok(self
+            .replace_with_changes(html)
+            .await?
+            .map(|(replaced, _)| replaced))
+    }
+
+    /// 包んでいる置換処理の記録を返す. テンプレートの引数の中の変更には、テンプレート名と引数名を付ける
+    async fn replace_with_changes(
+        &self,
+        html: ImmutableWikicode,
+    ) -> anyhow::Result<Option<(ImmutableWikicode, Vec<ChangeRecord>)>> {
+        let (replaced, mut changes) = self.replacers.replace_all(html).await?;
 
         let templates = replaced
             .clone()
@@ -57,6 +68,7 @@ where
 
         let html = replaced.into_mutable();
         let templates = html.filter_templates()?;
+        let mut is_changed = !changes.is_empty();
 
         for (index, params) in replaced_templates {
             let template = &templates[index];
@@ -66,9 +78,22 @@ where
                 continue;
             }
 
+            let name = template.name();
+            let name = name.trim_start_matches("Template:");
             let new_params = params
                 .into_iter()
-                .map(|(k, v)| (k.clone(), v.unwrap_or_else(|| old_params[&k].clone())))
+                .map(|(k, v)| match v {
+                    Some((v, records)) => {
+                        changes.extend(records.into_iter().map(|record| {
+                            ChangeRecord::new(
+                                record.replacer,
+                                format!("{{{{{name}}}}}の引数 {k}: {}", record.description),
+                            )
+                        }));
+                        (k, v)
+                    }
+                    None => (k.clone(), old_params[&k].clone()),
+                })
                 .collect::<IndexMap<_, _>>();
 
             let _ = templates[index].set_params(new_params);
@@ -76,7 +101,7 @@ where
         }
 
         if is_changed {
-            Ok(Some(html.into_immutable()))
+            Ok(Some((html.into_immutable(), changes)))
         } else {
             Ok(None)
         }
@@ -105,7 +130,7 @@ where
 fn replace_params<'s, 'r: 's, S, Replacer>(
     stream: S,
     replacer: Replacer,
-) -> impl Stream<Item = anyhow::Result<(String, Option<ImmutableWikicode>)>> + 's
+) -> impl Stream<Item = anyhow::Result<(String, Option<(ImmutableWikicode, Vec<ChangeRecord>)>)>> + 's
 where
     S: Stream<Item = anyhow::Result<(String, ImmutableWikicode)>> + 's,
     Replacer: CategoryReplacer + Clone + 'r,
@@ -113,7 +138,7 @@ where
     stream.and_then(move |(k, v)| {
         let replacer = replacer.clone();
         async move {
-            let replaced = replacer.replace(v).await?;
+            let replaced = replacer.replace_with_changes(v).await?;
 
             Ok((k, replaced))
         }
@@ -123,16 +148,19 @@ where
 fn params_to_wikitext<'s, S>(
     stream: S,
     bot: &'s Bot,
-) -> impl Stream<Item = anyhow::Result<(String, Option<String>)>> + 's
+) -> impl Stream<Item = anyhow::Result<(String, Option<(String, Vec<ChangeRecord>)>)>> + 's
 where
-    S: Stream<Item = anyhow::Result<(String, Option<ImmutableWikicode>)>> + 's,
+    S: Stream<Item = anyhow::Result<(String, Option<(ImmutableWikicode, Vec<ChangeRecord>)>)>> + 's,
 {
     stream.and_then(|(k, v)| async {
         let bot = bot.clone();
 
         let v = tokio::spawn(async move {
             match v {
-                Some(v) => anyhow::Ok(Some(bot.parsoid().transform_to_wikitext(&v).await?)),
+                Some((v, records)) => anyhow::Ok(Some((
+                    bot.parsoid().transform_to_wikitext(&v).await?,
+                    records,
+                ))),
                 None => Ok(None),
             }
         })
@@ -149,6 +177,7 @@ mod tests {
 
     use super::*;
     use crate::replacer::template::image_requested::ImageRequestedReplacer;
+    use crate::title::CategoryNormalizer;
     use crate::util::test;
 
     #[tokio::test]
@@ -204,11 +233,21 @@ mod tests {
 
         let replacer = hlist![RecursionReplacer::new(
             bot.clone(),
-            hlist![ImageRequestedReplacer::new(from, to)],
+            hlist![ImageRequestedReplacer::new(
+                from,
+                to,
+                CategoryNormalizer::new(&bot)
+            )],
         )];
-        let (replaced_html, is_changed) = replacer.replace_all(html).await?;
+        let (replaced_html, changes) = replacer.replace_all(html).await?;
 
-        assert!(is_changed);
+        assert_eq!(
+            changes,
+            [ChangeRecord::new(
+                "ImageRequestedReplacer",
+                "{{専修学校}}の引数 画像: {{画像募集中}}: cat = 伊達市 (北海道) → 北海道伊達市",
+            )]
+        );
 
         let replaced_wikicode = bot.parsoid().transform_to_wikitext(&replaced_html).await?;
 
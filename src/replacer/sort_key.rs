@@ -0,0 +1,185 @@
+use mwbot::parsoid::prelude::*;
+
+use crate::replacer::CategoryReplacer;
+use crate::title::CategoryNormalizer;
+
+/// ソートキーの変更規則
+#[derive(Debug, Clone, PartialEq)]
+pub enum SortKeyRule {
+    /// 個別のソートキーを除去し、`{{DEFAULTSORT}}` に従わせる
+    DefaultSort,
+    /// ソートキーの先頭から指定した文字列を除去する
+    RemovePrefix(String),
+}
+
+impl SortKeyRule {
+    /// `ソートキー: DEFAULTSORT` や `ソートキー: 接頭辞「あ」を除去` 形式の行から規則を読み取る
+    pub fn parse(line: &str) -> Option<Self> {
+        let rule = line
+            .trim()
+            .strip_prefix("ソートキー")?
+            .trim_start()
+            .strip_prefix([':', '：'])?
+            .trim();
+
+        if rule == "DEFAULTSORT" {
+            return Some(Self::DefaultSort);
+        }
+
+        let prefix = rule.strip_prefix("接頭辞「")?.strip_suffix("」を除去")?;
+        if prefix.is_empty() {
+            return None;
+        }
+        Some(Self::RemovePrefix(prefix.to_string()))
+    }
+
+    /// 規則を適用した後のソートキーを返す
+    pub fn apply(&self, sort_key: Option<&str>) -> Option<String> {
+        match self {
+            Self::DefaultSort => None,
+            Self::RemovePrefix(prefix) => sort_key
+                .map(|key| key.strip_prefix(prefix.as_str()).unwrap_or(key))
+                .filter(|key| !key.is_empty())
+                .map(ToString::to_string),
+        }
+    }
+}
+
+/// カテゴリタグ(`[[Category:Example|key]]`)のソートキーの変更
+#[derive(Debug, Clone)]
+pub struct SortKeyReplacer {
+    category: String,
+    rule: SortKeyRule,
+    normalizer: CategoryNormalizer,
+}
+
+impl SortKeyReplacer {
+    pub fn new(category: String, rule: SortKeyRule, normalizer: CategoryNormalizer) -> Self {
+        Self {
+            category,
+            rule,
+            normalizer,
+        }
+    }
+}
+
+impl CategoryReplacer for SortKeyReplacer {
+    async fn replace(&self, html: ImmutableWikicode) -> anyhow::Result<Option<ImmutableWikicode>> {
+        let html = html.into_mutable();
+
+        let mut is_changed = false;
+        for category in html.filter_categories().into_iter().filter(|category| {
+            self.normalizer
+                .is_same(&category.category(), &self.category)
+        }) {
+            let sort_key = category.sort_key();
+            let new_sort_key = self.rule.apply(sort_key.as_deref());
+            if new_sort_key != sort_key {
+                category.set_sort_key(new_sort_key.as_deref());
+                is_changed = true;
+            }
+        }
+
+        if is_changed {
+            Ok(Some(html.into_immutable()))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use indoc::indoc;
+    use pretty_assertions::assert_str_eq;
+    use rstest::rstest;
+
+    use crate::replacer::sort_key::{SortKeyReplacer, SortKeyRule};
+    use crate::replacer::CategoryReplacer;
+    use crate::title::CategoryNormalizer;
+    use crate::util::test;
+
+    #[rstest]
+    #[case("ソートキー: DEFAULTSORT", Some(SortKeyRule::DefaultSort))]
+    #[case("ソートキー：DEFAULTSORT", Some(SortKeyRule::DefaultSort))]
+    #[case(
+        "ソートキー: 接頭辞「廃」を除去",
+        Some(SortKeyRule::RemovePrefix("廃".to_string()))
+    )]
+    #[case("ソートキー: 接頭辞「」を除去", None)]
+    #[case("ソートキー: 不明な規則", None)]
+    #[case("議論を参照", None)]
+    fn test_parse_rule(#[case] line: &str, #[case] expected: Option<SortKeyRule>) {
+        assert_eq!(SortKeyRule::parse(line), expected);
+    }
+
+    #[rstest]
+    #[case(
+        SortKeyRule::DefaultSort,
+        indoc! {"\
+            [[Category:Name1|ほげ]]
+            [[Category:Name2|ふが]]
+        "},
+        indoc! {"\
+            [[Category:Name1]]
+            [[Category:Name2|ふが]]
+        "},
+        true,
+    )]
+    #[case(
+        SortKeyRule::RemovePrefix("廃".to_string()),
+        indoc! {"\
+            [[Category:Name1|廃ふくいしりつふくい]]
+        "},
+        indoc! {"\
+            [[Category:Name1|ふくいしりつふくい]]
+        "},
+        true,
+    )]
+    #[case(
+        SortKeyRule::RemovePrefix("廃".to_string()),
+        indoc! {"\
+            [[Category:Name1|ふくいしりつふくい]]
+        "},
+        "",
+        false,
+    )]
+    #[case(
+        SortKeyRule::DefaultSort,
+        indoc! {"\
+            [[Category:Name1]]
+        "},
+        "",
+        false,
+    )]
+    #[tokio::test]
+    async fn test_replace(
+        #[case] rule: SortKeyRule,
+        #[case] before_wikitext: &str,
+        #[case] after_wikitext: &str,
+        #[case] should_be_changed: bool,
+    ) -> anyhow::Result<()> {
+        let bot = test::bot().await;
+
+        let html = bot.parsoid().transform_to_html(before_wikitext).await?;
+
+        let replacer = SortKeyReplacer::new(
+            "Category:Name1".to_string(),
+            rule,
+            CategoryNormalizer::new(&bot),
+        );
+        let replaced = replacer.replace(html).await?;
+
+        if should_be_changed {
+            let replaced_wikitext = bot
+                .parsoid()
+                .transform_to_wikitext(&replaced.expect("wikitext should be changed"))
+                .await?;
+            assert_str_eq!(after_wikitext, replaced_wikitext);
+        } else {
+            assert!(replaced.is_none());
+        }
+
+        Ok(())
+    }
+}
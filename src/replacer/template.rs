@@ -1,2 +1,3 @@
 pub(super) mod category_of_redirects;
 pub(super) mod image_requested;
+pub(super) mod template_parameter;
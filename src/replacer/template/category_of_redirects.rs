@@ -4,16 +4,22 @@ use regex::Regex;
 use tracing::warn;
 
 use crate::replacer::CategoryReplacer;
+use crate::title::CategoryNormalizer;
 
 #[derive(Debug, Clone)]
 pub struct CategoryOfRedirectsReplacer {
     from: String,
     to: Vec<String>,
+    normalizer: CategoryNormalizer,
 }
 
 impl CategoryOfRedirectsReplacer {
-    pub fn new(from: String, to: Vec<String>) -> Self {
-        Self { from, to }
+    pub fn new(from: String, to: Vec<String>, normalizer: CategoryNormalizer) -> Self {
+        Self {
+            from,
+            to,
+            normalizer,
+        }
     }
 }
 
@@ -92,7 +98,10 @@ impl Internal for CategoryOfRedirectsReplacer {
             .filter(|(k, _v)| k.parse::<u32>().is_err())
             .collect::<IndexMap<_, _>>();
 
-        let Some(index) = categories.iter().position(|param| *param == self.from) else {
+        let Some(index) = categories
+            .iter()
+            .position(|param| self.normalizer.is_same(param, &self.from))
+        else {
             return Ok(false);
         };
 
@@ -153,7 +162,7 @@ impl Internal for CategoryOfRedirectsReplacer {
                 .collect::<Vec<_>>();
             if let Some(index) = categories
                 .iter()
-                .position(|cat| *cat == self.from.replace("Category:", ""))
+                .position(|cat| self.normalizer.is_same(cat, &self.from))
             {
                 categories.remove(index);
                 self.to
@@ -218,10 +227,14 @@ mod test {
 
         let html = bot.parsoid().transform_to_html(before).await?;
 
-        let replacer = hlist![CategoryOfRedirectsReplacer::new(from, to)];
-        let (replaced_html, is_changed) = replacer.replace_all(html).await?;
+        let replacer = hlist![CategoryOfRedirectsReplacer::new(
+            from,
+            to,
+            CategoryNormalizer::new(&bot)
+        )];
+        let (replaced_html, changes) = replacer.replace_all(html).await?;
 
-        assert!(is_changed);
+        assert!(!changes.is_empty());
 
         let replaced_wikicode = bot.parsoid().transform_to_wikitext(&replaced_html).await?;
         assert_eq!(after, replaced_wikicode);
@@ -246,10 +259,14 @@ mod test {
 
         let html = bot.parsoid().transform_to_html(before).await?;
 
-        let replacer = hlist![CategoryOfRedirectsReplacer::new(from, to)];
-        let (replaced_html, is_changed) = replacer.replace_all(html).await?;
+        let replacer = hlist![CategoryOfRedirectsReplacer::new(
+            from,
+            to,
+            CategoryNormalizer::new(&bot)
+        )];
+        let (replaced_html, changes) = replacer.replace_all(html).await?;
 
-        assert!(is_changed);
+        assert!(!changes.is_empty());
 
         let replaced_wikicode = bot.parsoid().transform_to_wikitext(&replaced_html).await?;
         assert_eq!(after, replaced_wikicode);
@@ -272,10 +289,14 @@ mod test {
 
         let html = bot.parsoid().transform_to_html(before).await?;
 
-        let replacer = hlist![CategoryOfRedirectsReplacer::new(from, to)];
-        let (replaced_html, is_changed) = replacer.replace_all(html).await?;
+        let replacer = hlist![CategoryOfRedirectsReplacer::new(
+            from,
+            to,
+            CategoryNormalizer::new(&bot)
+        )];
+        let (replaced_html, changes) = replacer.replace_all(html).await?;
 
-        assert!(is_changed);
+        assert!(!changes.is_empty());
 
         let replaced_wikicode = bot.parsoid().transform_to_wikitext(&replaced_html).await?;
         assert_eq!(after, replaced_wikicode);
@@ -312,7 +333,7 @@ mod test {
             .into_mutable();
         let template = &html.filter_templates()?[0];
 
-        let replacer = CategoryOfRedirectsReplacer::new(from, to);
+        let replacer = CategoryOfRedirectsReplacer::new(from, to, CategoryNormalizer::new(&bot));
         let is_changed = replacer.replace_internal_complex(template)?;
         assert!(is_changed);
 
@@ -346,7 +367,7 @@ mod test {
             .into_mutable();
         let template = &html.filter_templates()?[0];
 
-        let replacer = CategoryOfRedirectsReplacer::new(from, to);
+        let replacer = CategoryOfRedirectsReplacer::new(from, to, CategoryNormalizer::new(&bot));
         let is_changed = replacer.replace_internal_complex(template)?;
         assert!(is_changed);
 
@@ -377,7 +398,7 @@ mod test {
             .into_mutable();
         let template = &html.filter_templates()?[0];
 
-        let replacer = CategoryOfRedirectsReplacer::new(from, to);
+        let replacer = CategoryOfRedirectsReplacer::new(from, to, CategoryNormalizer::new(&bot));
         let is_changed = replacer.replace_internal_complex(template)?;
         assert!(is_changed);
 
@@ -407,10 +428,11 @@ mod test {
         let replacers = hlist![CategoryOfRedirectsReplacer::new(
             "Category:ネコ".to_string(),
             vec!["Category:猫".to_string()],
+            CategoryNormalizer::new(&bot),
         )];
-        let (_replaced, is_changed) = replacers.replace_all(html).await?;
+        let (_replaced, changes) = replacers.replace_all(html).await?;
 
-        assert!(!is_changed);
+        assert!(changes.is_empty());
 
         Ok(())
     }
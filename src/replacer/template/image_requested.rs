@@ -2,6 +2,7 @@ use indexmap::IndexMap;
 use mwbot::parsoid::prelude::*;
 
 use crate::replacer::CategoryReplacer;
+use crate::title::CategoryNormalizer;
 
 const TEMPLATES: &[&str] = &[
     "Template:画像提供依頼",
@@ -13,10 +14,11 @@ const TEMPLATES: &[&str] = &[
 pub struct ImageRequestedReplacer {
     from: String,
     to: Vec<String>,
+    normalizer: CategoryNormalizer,
 }
 
 impl ImageRequestedReplacer {
-    pub fn new(from: String, to: Vec<String>) -> Option<Self> {
+    pub fn new(from: String, to: Vec<String>, normalizer: CategoryNormalizer) -> Option<Self> {
         if !from.ends_with("の画像提供依頼") || to.iter().any(|t| !t.ends_with("の画像提供依頼"))
         {
             return None;
@@ -37,6 +39,7 @@ impl ImageRequestedReplacer {
         Some(Self {
             from: from.to_string(),
             to,
+            normalizer,
         })
     }
 }
@@ -68,7 +71,10 @@ impl CategoryReplacer for ImageRequestedReplacer {
                 })
                 .collect::<Vec<_>>();
 
-            let Some(index) = cats.iter().position(|c| *c == self.from) else {
+            let Some(index) = cats
+                .iter()
+                .position(|c| self.normalizer.is_same(c, &self.from))
+            else {
                 return Ok(None);
             };
             cats.remove(index);
@@ -76,7 +82,11 @@ impl CategoryReplacer for ImageRequestedReplacer {
 
             self.to
                 .iter()
-                .filter(|c| !already_added_cats.contains(&c.to_string()))
+                .filter(|c| {
+                    !already_added_cats
+                        .iter()
+                        .any(|added| self.normalizer.is_same(added, c))
+                })
                 .enumerate()
                 .for_each(|(i, cat)| {
                     cats.insert(index + i, cat.to_string());
@@ -131,10 +141,14 @@ mod test {
 
         let html = bot.parsoid().transform_to_html(before).await?;
 
-        let replacer = hlist![ImageRequestedReplacer::new(from, to)];
-        let (replaced_html, is_changed) = replacer.replace_all(html).await?;
+        let replacer = hlist![ImageRequestedReplacer::new(
+            from,
+            to,
+            CategoryNormalizer::new(&bot)
+        )];
+        let (replaced_html, changes) = replacer.replace_all(html).await?;
 
-        assert!(is_changed);
+        assert!(!changes.is_empty());
 
         let replaced_wikicode = bot.parsoid().transform_to_wikitext(&replaced_html).await?;
         assert_eq!(after, replaced_wikicode);
@@ -164,10 +178,14 @@ mod test {
 
         let html = bot.parsoid().transform_to_html(before).await?;
 
-        let replacer = hlist![ImageRequestedReplacer::new(from, to)];
-        let (replaced_html, is_changed) = replacer.replace_all(html).await?;
+        let replacer = hlist![ImageRequestedReplacer::new(
+            from,
+            to,
+            CategoryNormalizer::new(&bot)
+        )];
+        let (replaced_html, changes) = replacer.replace_all(html).await?;
 
-        assert!(is_changed);
+        assert!(!changes.is_empty());
 
         let replaced_wikicode = bot.parsoid().transform_to_wikitext(&replaced_html).await?;
         assert_eq!(after, replaced_wikicode);
@@ -197,10 +215,11 @@ mod test {
 
         let html = bot.parsoid().transform_to_html(before).await?;
 
-        let replacer = hlist![ImageRequestedReplacer::new(from, to).unwrap()];
-        let (replaced_html, is_changed) = replacer.replace_all(html).await?;
+        let replacer =
+            hlist![ImageRequestedReplacer::new(from, to, CategoryNormalizer::new(&bot)).unwrap()];
+        let (replaced_html, changes) = replacer.replace_all(html).await?;
 
-        assert!(is_changed);
+        assert!(!changes.is_empty());
 
         let replaced_wikicode = bot.parsoid().transform_to_wikitext(&replaced_html).await?;
         assert_eq!(after, replaced_wikicode);
@@ -227,10 +246,14 @@ mod test {
 
         let html = bot.parsoid().transform_to_html(before).await?;
 
-        let replacer = hlist![ImageRequestedReplacer::new(from, to)];
-        let (replaced_html, is_changed) = replacer.replace_all(html).await?;
+        let replacer = hlist![ImageRequestedReplacer::new(
+            from,
+            to,
+            CategoryNormalizer::new(&bot)
+        )];
+        let (replaced_html, changes) = replacer.replace_all(html).await?;
 
-        assert!(is_changed);
+        assert!(!changes.is_empty());
 
         let replaced_wikicode = bot.parsoid().transform_to_wikitext(&replaced_html).await?;
         assert_eq!(after, replaced_wikicode);
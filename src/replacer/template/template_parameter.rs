@@ -0,0 +1,372 @@
+use mwbot::parsoid::prelude::*;
+
+use crate::config::TemplateParameterRule;
+use crate::replacer::CategoryReplacer;
+use crate::title::CategoryNormalizer;
+
+/// Wiki上の設定の [`TemplateParameterRule`] に従い、テンプレートの引数のカテゴリ名を置換する.
+/// `to` が空の場合、引数を削除する
+#[derive(Debug, Clone)]
+pub struct TemplateParameterReplacer {
+    from: String,
+    to: Vec<String>,
+    rule: TemplateParameterRule,
+    normalizer: CategoryNormalizer,
+}
+
+impl TemplateParameterReplacer {
+    /// カテゴリ名が規則の接頭辞・接尾辞に当てはまらない場合や、
+    /// 番号付きでない引数に複数のカテゴリを指定する場合は `None` を返す
+    pub fn new(
+        from: String,
+        to: Vec<String>,
+        rule: TemplateParameterRule,
+        normalizer: CategoryNormalizer,
+    ) -> Option<Self> {
+        if !rule.numbered && to.len() > 1 {
+            return None;
+        }
+
+        let from = param_value(&rule, &from)?;
+        let to = to
+            .iter()
+            .map(|to| param_value(&rule, to))
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(Self {
+            from,
+            to,
+            rule,
+            normalizer,
+        })
+    }
+}
+
+impl CategoryReplacer for TemplateParameterReplacer {
+    async fn replace(&self, html: ImmutableWikicode) -> anyhow::Result<Option<ImmutableWikicode>> {
+        let html = html.into_mutable();
+
+        let templates = html.filter_templates()?;
+        let mut is_changed = false;
+        for template in templates
+            .into_iter()
+            .filter(|template| self.rule.templates.contains(&template.name()))
+        {
+            let params = template.params();
+
+            // カテゴリ名の引数の名前と値
+            let category_params = params
+                .iter()
+                .filter(|(key, _)| is_category_param(&self.rule, key))
+                .collect::<Vec<_>>();
+            let mut values = category_params
+                .iter()
+                .map(|(_, value)| value.trim().to_string())
+                .collect::<Vec<_>>();
+
+            let Some(index) = values
+                .iter()
+                .position(|value| self.normalizer.is_same(value, &self.from))
+            else {
+                continue;
+            };
+            values.remove(index);
+            let already_added = values.clone();
+
+            self.to
+                .iter()
+                .filter(|to| {
+                    !already_added
+                        .iter()
+                        .any(|added| self.normalizer.is_same(added, to))
+                })
+                .enumerate()
+                .for_each(|(i, to)| values.insert(index + i, to.to_string()));
+
+            // 差分を小さくするため、既存の引数は位置を変えずに値のみ書き換える.
+            // 増えた分は空いている番号の引数として呼び出しの末尾に加え、減った分は削除する
+            let mut unused_names = (0..)
+                .map(|i| param_name(&self.rule, i))
+                .filter(|name| !params.contains_key(name));
+            for (i, value) in values.iter().enumerate() {
+                match category_params.get(i) {
+                    Some((key, old)) if old.trim() != value => {
+                        template.set_param(key, &with_spacing_of(old, value))?;
+                    }
+                    Some(_) => {}
+                    None => {
+                        let name = unused_names.next().expect("infinite names");
+                        let spacing = category_params.last().map_or("", |(_, old)| old.as_str());
+                        template.set_param(&name, &with_spacing_of(spacing, value))?;
+                    }
+                }
+            }
+            for (key, _) in category_params.iter().skip(values.len()) {
+                template.remove_param(key)?;
+            }
+            is_changed = true;
+        }
+
+        Ok(is_changed.then(|| html.into_immutable()))
+    }
+}
+
+/// `old` の前後の空白を保ったまま、値を `value` にする
+fn with_spacing_of(old: &str, value: &str) -> String {
+    let leading = &old[..old.len() - old.trim_start().len()];
+    let trailing = &old[old.trim_end().len()..];
+    format!("{leading}{value}{trailing}")
+}
+
+/// カテゴリ名から規則の接頭辞・接尾辞を除き、引数の値にする
+fn param_value(rule: &TemplateParameterRule, category: &str) -> Option<String> {
+    let value = category
+        .trim_start_matches("Category:")
+        .strip_prefix(rule.prefix.as_str())?
+        .strip_suffix(rule.suffix.as_str())?;
+
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+/// カテゴリ名の一部を持つ引数であるか. 番号付きの場合は `cat` に加えて `cat2` なども含む
+fn is_category_param(rule: &TemplateParameterRule, key: &str) -> bool {
+    match key.strip_prefix(rule.param.as_str()) {
+        Some("") => true,
+        Some(number) => rule.numbered && number.chars().all(|c| c.is_ascii_digit()),
+        None => false,
+    }
+}
+
+/// `index` 番目のカテゴリの引数名. 1番目は番号を付けない
+fn param_name(rule: &TemplateParameterRule, index: usize) -> String {
+    if index == 0 {
+        rule.param.clone()
+    } else {
+        format!("{}{}", rule.param, index + 1)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use frunk_core::hlist;
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+
+    use super::*;
+    use crate::replacer::CategoryReplacerList;
+    use crate::util::test;
+
+    fn rule(numbered: bool) -> TemplateParameterRule {
+        TemplateParameterRule {
+            templates: vec!["Template:Navbox".to_string()],
+            param: "cat".to_string(),
+            numbered,
+            prefix: String::new(),
+            suffix: "の人物".to_string(),
+        }
+    }
+
+    #[rstest]
+    #[case("cat", false, true)]
+    #[case("cat2", false, false)]
+    #[case("cat2", true, true)]
+    #[case("category", true, false)]
+    #[case("date", true, false)]
+    fn test_is_category_param(#[case] key: &str, #[case] numbered: bool, #[case] expected: bool) {
+        assert_eq!(is_category_param(&rule(numbered), key), expected);
+    }
+
+    /// 県のスタブテンプレートのような、番号付きでない別の規則
+    fn stub_rule() -> TemplateParameterRule {
+        TemplateParameterRule {
+            templates: vec![
+                "Template:Pref-stub".to_string(),
+                "Template:県スタブ".to_string(),
+            ],
+            param: "pref".to_string(),
+            numbered: false,
+            prefix: "日本の".to_string(),
+            suffix: "関連のスタブ".to_string(),
+        }
+    }
+
+    #[rstest]
+    #[case("Category:北海道の人物", Some("北海道"))]
+    #[case("Category:北海道の画像提供依頼", None)]
+    #[case("Category:の人物", None)]
+    fn test_param_value(#[case] category: &str, #[case] expected: Option<&str>) {
+        assert_eq!(param_value(&rule(false), category).as_deref(), expected);
+    }
+
+    #[rstest]
+    #[case("Category:日本の北海道関連のスタブ", Some("北海道"))]
+    #[case("Category:北海道関連のスタブ", None)]
+    fn test_param_value_with_prefix(#[case] category: &str, #[case] expected: Option<&str>) {
+        assert_eq!(param_value(&stub_rule(), category).as_deref(), expected);
+    }
+
+    #[rstest]
+    #[case("北海道\n", "道央", "道央\n")]
+    #[case(" 北海道 ", "道央", " 道央 ")]
+    #[case("北海道", "道央", "道央")]
+    fn test_with_spacing_of(#[case] old: &str, #[case] value: &str, #[case] expected: &str) {
+        assert_eq!(with_spacing_of(old, value), expected);
+    }
+
+    #[rstest]
+    #[case(
+        "Category:北海道の人物",
+        &["Category:道央の人物", "Category:道南の人物"],
+        true,
+        indoc! {"
+            {{Navbox
+            |name=Example
+            |cat=道央
+            |cat2=道南
+            }}
+        "},
+    )]
+    #[case(
+        "Category:北海道の人物",
+        &["Category:道央の人物"],
+        false,
+        indoc! {"
+            {{Navbox
+            |name=Example
+            |cat=道央
+            }}
+        "},
+    )]
+    #[case(
+        "Category:北海道の人物",
+        &[],
+        false,
+        indoc! {"
+            {{Navbox
+            |name=Example
+            }}
+        "},
+    )]
+    #[tokio::test]
+    async fn test_replace(
+        #[case] from: &str,
+        #[case] to: &[&str],
+        #[case] numbered: bool,
+        #[case] after: &str,
+    ) -> anyhow::Result<()> {
+        let bot = test::bot().await;
+        let to = to.iter().map(ToString::to_string).collect();
+
+        let before = indoc! {"
+            {{Navbox
+            |name=Example
+            |cat=北海道
+            }}
+        "};
+        let html = bot.parsoid().transform_to_html(before).await?;
+
+        let replacer = hlist![TemplateParameterReplacer::new(
+            from.to_string(),
+            to,
+            rule(numbered),
+            CategoryNormalizer::new(&bot)
+        )
+        .expect("rule should match")];
+        let (replaced_html, changes) = replacer.replace_all(html).await?;
+
+        assert!(!changes.is_empty());
+
+        let replaced_wikicode = bot.parsoid().transform_to_wikitext(&replaced_html).await?;
+        assert_eq!(after, replaced_wikicode);
+
+        Ok(())
+    }
+
+    /// 引数の値を書き換えても、引数の並びは変えない
+    #[tokio::test]
+    async fn test_replace_keeps_param_order() -> anyhow::Result<()> {
+        let bot = test::bot().await;
+
+        let before = indoc! {"
+            {{Navbox
+            |cat=北海道
+            |name=Example
+            |cat2=道南
+            |list1=本文
+            }}
+        "};
+        let html = bot.parsoid().transform_to_html(before).await?;
+
+        let replacer = hlist![TemplateParameterReplacer::new(
+            "Category:北海道の人物".to_string(),
+            vec![
+                "Category:道央の人物".to_string(),
+                "Category:道北の人物".to_string()
+            ],
+            rule(true),
+            CategoryNormalizer::new(&bot)
+        )
+        .expect("rule should match")];
+        let (replaced_html, _) = replacer.replace_all(html).await?;
+
+        let replaced_wikicode = bot.parsoid().transform_to_wikitext(&replaced_html).await?;
+        assert_eq!(
+            indoc! {"
+                {{Navbox
+                |cat=道央
+                |name=Example
+                |cat2=道南
+                |list1=本文
+                |cat3=道北
+                }}
+            "},
+            replaced_wikicode
+        );
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(
+        "{{Pref-stub|pref=北海道}}\n",
+        &["Category:日本の青森県関連のスタブ"],
+        "{{Pref-stub|pref=青森県}}\n",
+    )]
+    #[case(
+        "{{県スタブ|pref=北海道|size=small}}\n",
+        &[],
+        "{{県スタブ|size=small}}\n",
+    )]
+    #[case(
+        "{{Navbox|cat=北海道}}{{Pref-stub|pref=北海道}}\n",
+        &["Category:日本の青森県関連のスタブ"],
+        "{{Navbox|cat=北海道}}{{Pref-stub|pref=青森県}}\n",
+    )]
+    #[tokio::test]
+    async fn test_replace_with_another_rule(
+        #[case] before: &str,
+        #[case] to: &[&str],
+        #[case] after: &str,
+    ) -> anyhow::Result<()> {
+        let bot = test::bot().await;
+        let to = to.iter().map(ToString::to_string).collect();
+        let html = bot.parsoid().transform_to_html(before).await?;
+
+        let replacer = hlist![TemplateParameterReplacer::new(
+            "Category:日本の北海道関連のスタブ".to_string(),
+            to,
+            stub_rule(),
+            CategoryNormalizer::new(&bot)
+        )
+        .expect("rule should match")];
+        let (replaced_html, changes) = replacer.replace_all(html).await?;
+
+        assert!(!changes.is_empty());
+
+        let replaced_wikicode = bot.parsoid().transform_to_wikitext(&replaced_html).await?;
+        assert_eq!(after, replaced_wikicode);
+
+        Ok(())
+    }
+}
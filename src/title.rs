@@ -0,0 +1,204 @@
+use std::sync::Arc;
+
+use mwbot::Bot;
+use mwtitle::TitleCodec;
+use serde_json::Value;
+
+const NS_CATEGORY: i32 = 14;
+
+/// カテゴリ名を比較できる形に正規化する.
+/// 名前空間の別名(`カテゴリ:`)や先頭の小文字、`_` と空白の違いはwikiのsiteinfoに従って解決する.
+/// 全角と半角の括弧の違いは比較する時のみ無視し、返すタイトルは変えない
+#[derive(Debug, Clone)]
+pub struct CategoryNormalizer {
+    codec: Arc<TitleCodec>,
+}
+
+impl CategoryNormalizer {
+    pub fn new(bot: &Bot) -> Self {
+        Self::with_codec(bot.title_codec().clone())
+    }
+
+    fn with_codec(codec: TitleCodec) -> Self {
+        Self {
+            codec: Arc::new(codec),
+        }
+    }
+
+    /// `Category:名前` の形にする. カテゴリ名前空間のタイトルでない場合は `None` を返す
+    pub fn normalize(&self, title: &str) -> Option<String> {
+        let title = self.codec.new_title(title).ok()?;
+        (title.namespace() == NS_CATEGORY).then(|| self.codec.to_pretty(&title))
+    }
+
+    /// [`Self::normalize`] と同様だが、名前空間のないカテゴリ名はカテゴリ名前空間とみなす
+    pub fn normalize_name(&self, name: &str) -> Option<String> {
+        let title = self
+            .codec
+            .new_title_with_namespace(name, NS_CATEGORY)
+            .ok()?;
+        (title.namespace() == NS_CATEGORY).then(|| self.codec.to_pretty(&title))
+    }
+
+    /// `a` と `b` が同じカテゴリを指すか. 名前空間のないカテゴリ名も比較できる
+    pub fn is_same(&self, a: &str, b: &str) -> bool {
+        match (self.comparison_key(a), self.comparison_key(b)) {
+            (Some(a), Some(b)) => a == b,
+            _ => false,
+        }
+    }
+
+    /// 比較に使う形. [`Self::is_same`] で同じカテゴリとされる名前は同じ値になる
+    pub fn comparison_key(&self, name: &str) -> Option<String> {
+        self.normalize_name(name)
+            .map(|name| unify_parentheses(&name))
+    }
+}
+
+/// カテゴリ名前空間の名前、正規名、別名(`カテゴリ` など)をwikiのsiteinfoから取得する
+pub async fn category_namespace_names(bot: &Bot) -> mwbot::Result<Vec<String>> {
+    let params = vec![
+        ("action", "query".to_string()),
+        ("meta", "siteinfo".to_string()),
+        ("siprop", "namespaces|namespacealiases".to_string()),
+    ];
+
+    let resp = bot.api().get_value(params).await?;
+    Ok(namespace_names(&resp["query"], NS_CATEGORY))
+}
+
+/// siteinfoの `namespaces` と `namespacealiases` から、名前空間 `id` の名前を重複なく集める
+fn namespace_names(siteinfo: &Value, id: i32) -> Vec<String> {
+    let namespace = &siteinfo["namespaces"][id.to_string()];
+    let aliases = siteinfo["namespacealiases"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|alias| alias["id"].as_i64() == Some(id.into()))
+        .map(|alias| &alias["alias"]);
+
+    let mut names = Vec::new();
+    for name in [&namespace["name"], &namespace["canonical"]]
+        .into_iter()
+        .chain(aliases)
+        .filter_map(Value::as_str)
+    {
+        if !name.is_empty() && !names.iter().any(|known| known == name) {
+            names.push(name.to_string());
+        }
+    }
+    names
+}
+
+/// 比較のため、全角の括弧を半角にそろえ、開き括弧の前の空白を除く.
+/// `伊達市（北海道）` と `伊達市 (北海道)` は同じ形になる
+fn unify_parentheses(title: &str) -> String {
+    let title = title.replace('（', "(").replace('）', ")");
+    let mut unified = String::with_capacity(title.len());
+    for c in title.chars() {
+        if c == '(' {
+            let trimmed = unified.trim_end().len();
+            unified.truncate(trimmed);
+        }
+        unified.push(c);
+    }
+    unified
+}
+
+#[cfg(test)]
+mod test {
+    use mwtitle::{SiteInfo, TitleCodec};
+    use rstest::rstest;
+    use serde_json::{json, Value};
+
+    use crate::title::{namespace_names, CategoryNormalizer, NS_CATEGORY};
+    use crate::util::test;
+
+    /// 日本語版ウィキペディアを模したsiteinfo
+    fn siteinfo() -> Value {
+        json!({
+            "general": {
+                "mainpage": "メインページ",
+                "lang": "ja",
+                "legaltitlechars": " %!\"$&'()*,\\-.\\/0-9:;=?@A-Z\\\\^_`a-z~\\x80-\\xFF+",
+            },
+            "namespaces": {
+                "0": { "id": 0, "case": "first-letter", "name": "" },
+                "10": { "id": 10, "case": "first-letter", "name": "Template", "canonical": "Template" },
+                "14": { "id": 14, "case": "first-letter", "name": "Category", "canonical": "Category" },
+            },
+            "namespacealiases": [
+                { "id": 10, "alias": "テンプレート" },
+                { "id": 14, "alias": "カテゴリ" },
+            ],
+        })
+    }
+
+    /// wikiに接続せずに作る
+    fn offline_normalizer() -> CategoryNormalizer {
+        let site_info = serde_json::from_value::<SiteInfo>(siteinfo()).unwrap();
+        CategoryNormalizer::with_codec(TitleCodec::from_site_info(site_info).unwrap())
+    }
+
+    #[test]
+    fn test_namespace_names() {
+        assert_eq!(
+            namespace_names(&siteinfo(), NS_CATEGORY),
+            ["Category", "カテゴリ"]
+        );
+    }
+
+    #[rstest]
+    #[case("カテゴリ:伊達市_(北海道)", Some("Category:伊達市 (北海道)"))]
+    #[case(":Category:name1", Some("Category:Name1"))]
+    #[case("伊達市", Some("Category:伊達市"))]
+    #[case("Template:伊達市", None)]
+    fn test_normalize_name_offline(#[case] name: &str, #[case] expected: Option<&str>) {
+        assert_eq!(
+            offline_normalizer().normalize_name(name).as_deref(),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case("Category:伊達市（北海道）", "伊達市 (北海道)", true)]
+    #[case("カテゴリ:name1", "Category:Name1", true)]
+    #[case("Category:Name1", "Category:Name2", false)]
+    #[case("Template:Name1", "Category:Name1", false)]
+    fn test_is_same_offline(#[case] a: &str, #[case] b: &str, #[case] expected: bool) {
+        assert_eq!(offline_normalizer().is_same(a, b), expected);
+    }
+
+    #[rstest]
+    #[case("Category:伊達市（北海道）", "Category:伊達市 (北海道)", true)]
+    #[case("伊達市（北海道）", "Category:伊達市(北海道)", true)]
+    #[case("Category:伊達市（北海道）", "Category:伊達市", false)]
+    #[tokio::test]
+    async fn test_unify_parentheses(#[case] a: &str, #[case] b: &str, #[case] expected: bool) {
+        let normalizer = CategoryNormalizer::new(&test::bot().await);
+        assert_eq!(normalizer.is_same(a, b), expected);
+    }
+
+    #[rstest]
+    #[case("Category:伊達市（北海道）", Some("Category:伊達市（北海道）"))]
+    #[case("カテゴリ:伊達市_(北海道)", Some("Category:伊達市 (北海道)"))]
+    #[case("Template:伊達市", None)]
+    #[tokio::test]
+    async fn test_normalize(#[case] title: &str, #[case] expected: Option<&str>) {
+        let normalizer = CategoryNormalizer::new(&test::bot().await);
+        assert_eq!(normalizer.normalize(title).as_deref(), expected);
+    }
+
+    #[rstest]
+    #[case("Category:伊達市 (北海道)", "Category:伊達市 (北海道)", true)]
+    #[case("カテゴリ:伊達市_(北海道)", "Category:伊達市 (北海道)", true)]
+    #[case(":Category:伊達市 (北海道)", "伊達市 (北海道)", true)]
+    #[case("Category:name1", "Category:Name1", true)]
+    #[case("Category:Name1", "Category:Name2", false)]
+    #[case("Template:Name1", "Category:Name1", false)]
+    #[tokio::test]
+    async fn test_is_same(#[case] a: &str, #[case] b: &str, #[case] expected: bool) {
+        let normalizer = CategoryNormalizer::new(&test::bot().await);
+        assert_eq!(normalizer.is_same(a, b), expected);
+    }
+}
@@ -1,5 +1,13 @@
+use std::time::Duration;
+
 use chrono::{DateTime, Utc};
 use mwbot::parsoid::prelude::*;
+use similar::TextDiff;
+use tokio::signal::unix::{self, SignalKind};
+use tokio::signal::{self};
+use tokio::sync::{watch, Mutex};
+use tokio::time::{self, Interval, MissedTickBehavior};
+use tracing::warn;
 
 pub trait ListExt {
     /// 順序なしリスト
@@ -82,15 +90,254 @@ impl DateTimeProvider for UtcDateTimeProvider {
     }
 }
 
+/// ページの保存間隔を制限する
+pub struct SaveLimiter {
+    interval: Option<Mutex<Interval>>,
+}
+
+impl SaveLimiter {
+    /// `interval` が0の場合は制限しない.
+    /// 保存が間に合わなかった間隔はまとめて取り戻さず、次の保存から再び `interval` を空ける
+    pub fn new(interval: Duration) -> Self {
+        let interval = (!interval.is_zero()).then(|| {
+            let mut interval = time::interval(interval);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            Mutex::new(interval)
+        });
+        Self { interval }
+    }
+
+    /// 前回の保存から `interval` が経過するまで待つ
+    pub async fn wait(&self) {
+        if let Some(interval) = &self.interval {
+            interval.lock().await.tick().await;
+        }
+    }
+}
+
+/// SIGTERMまたはSIGINTによる停止要求
+#[derive(Clone)]
+pub struct ShutdownSignal {
+    receiver: watch::Receiver<bool>,
+}
+
+impl ShutdownSignal {
+    /// シグナルの待ち受けを開始する
+    pub fn listen() -> anyhow::Result<Self> {
+        let mut sigterm = unix::signal(SignalKind::terminate())?;
+        let (sender, receiver) = watch::channel(false);
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = sigterm.recv() => {}
+                _ = signal::ctrl_c() => {}
+            }
+            warn!("Shutdown signal received. Stopping after the current page...");
+            let _ = sender.send(true);
+        });
+
+        Ok(Self { receiver })
+    }
+
+    /// 停止要求を受け取ったか
+    pub fn is_received(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    /// 停止要求を受け取るまで待つ
+    pub async fn wait(&self) {
+        let mut receiver = self.receiver.clone();
+        let _ = receiver.wait_for(|received| *received).await;
+    }
+}
+
+/// 差分の変更箇所の前後に表示する、変更のない行数
+const DIFF_CONTEXT_LINES: usize = 3;
+
+/// 差分を求める時間の上限. 超えた場合は最小でない差分になる
+const DIFF_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// 差分の最大文字数. 超えた部分は省略する
+const DIFF_MAX_CHARS: usize = 20_000;
+
+/// 行単位の差分をunified diff形式(削除行は `-`、追加行は `+`、変更のない行は空白で始まる)で返す.
+/// 変更箇所ごとに前後 [`DIFF_CONTEXT_LINES`] 行を付けたハンクにまとめ、
+/// [`DIFF_MAX_CHARS`] 文字を超える部分は省略する
+pub fn line_diff(before: &str, after: &str) -> String {
+    let diff = TextDiff::configure()
+        .timeout(DIFF_TIMEOUT)
+        .diff_lines(before, after);
+    let diff = diff
+        .unified_diff()
+        .context_radius(DIFF_CONTEXT_LINES)
+        .missing_newline_hint(false)
+        .to_string();
+
+    truncate_diff(diff.trim_end_matches('\n').to_string(), DIFF_MAX_CHARS)
+}
+
+/// `max_chars` 文字を超える場合、超えた行を省略する
+fn truncate_diff(diff: String, max_chars: usize) -> String {
+    if diff.chars().count() <= max_chars {
+        return diff;
+    }
+
+    let mut truncated = String::new();
+    let mut chars = 0;
+    for line in diff.lines() {
+        chars += line.chars().count() + 1;
+        if chars > max_chars {
+            break;
+        }
+        truncated.push_str(line);
+        truncated.push('\n');
+    }
+    truncated.push_str("(差分が長いため、以降を省略しました)");
+    truncated
+}
+
 #[cfg(test)]
 pub mod test {
     use std::path::Path;
+    use std::time::Duration;
 
+    use indoc::indoc;
     use mwbot::Bot;
+    use pretty_assertions::assert_str_eq;
+    use tokio::time::{self, Instant};
+
+    use crate::util::{line_diff, truncate_diff, SaveLimiter};
 
     pub async fn bot() -> Bot {
         Bot::from_path(Path::new("./mwbot.test.toml"))
             .await
             .unwrap()
     }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_save_limiter_after_slow_stretch() {
+        let interval = Duration::from_secs(10);
+        let limiter = SaveLimiter::new(interval);
+        limiter.wait().await;
+
+        // 間隔の数倍の時間が経過しても、続く保存の間は空ける
+        time::sleep(interval * 5).await;
+        limiter.wait().await;
+        let start = Instant::now();
+        limiter.wait().await;
+        limiter.wait().await;
+
+        assert!(Instant::now() - start >= interval * 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_save_limiter_disabled() {
+        let limiter = SaveLimiter::new(Duration::ZERO);
+        let start = Instant::now();
+        limiter.wait().await;
+        limiter.wait().await;
+
+        assert_eq!(Instant::now(), start);
+    }
+
+    #[test]
+    fn test_line_diff() {
+        let before = indoc! {"\
+            本文
+            [[Category:Name1]]
+            [[Category:Name3]]
+        "};
+        let after = indoc! {"\
+            本文
+            [[Category:Name2]]
+            [[Category:Name3]]
+        "};
+
+        assert_str_eq!(
+            line_diff(before, after),
+            indoc! {"\
+                @@ -1,3 +1,3 @@
+                 本文
+                -[[Category:Name1]]
+                +[[Category:Name2]]
+                 [[Category:Name3]]"}
+        );
+    }
+
+    #[test]
+    fn test_line_diff_with_unchanged_lines() {
+        let before = indoc! {"\
+            [[Category:Name1]]
+            [[Category:Name3]]
+            [[Category:Name4]]
+        "};
+        let after = indoc! {"\
+            [[Category:Name3]]
+            [[Category:Name2]]
+        "};
+
+        assert_str_eq!(
+            line_diff(before, after),
+            "@@ -1,3 +1,2 @@\n-[[Category:Name1]]\n [[Category:Name3]]\n-[[Category:Name4]]\n+[[Category:Name2]]"
+        );
+    }
+
+    #[test]
+    fn test_line_diff_separate_hunks() {
+        let body = (1..=20).map(|i| format!("本文{i}")).collect::<Vec<_>>();
+        let before = format!(
+            "{{{{Infobox|cat=A}}}}\n{}\n[[Category:A]]\n",
+            body.join("\n")
+        );
+        let after = format!(
+            "{{{{Infobox|cat=B}}}}\n{}\n[[Category:B]]\n",
+            body.join("\n")
+        );
+
+        assert_str_eq!(
+            line_diff(&before, &after),
+            indoc! {"\
+                @@ -1,4 +1,4 @@
+                -{{Infobox|cat=A}}
+                +{{Infobox|cat=B}}
+                 本文1
+                 本文2
+                 本文3
+                @@ -19,4 +19,4 @@
+                 本文18
+                 本文19
+                 本文20
+                -[[Category:A]]
+                +[[Category:B]]"}
+        );
+    }
+
+    #[test]
+    fn test_line_diff_truncated() {
+        let before = (0..3000)
+            .map(|i| format!("a{i}"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let after = (0..3000)
+            .map(|i| format!("b{i}"))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let diff = line_diff(&before, &after);
+        assert!(diff.starts_with("@@ -1,3000 +1,3000 @@\n-a0\n-a1\n"));
+        assert!(diff.ends_with("(差分が長いため、以降を省略しました)"));
+    }
+
+    #[test]
+    fn test_truncate_diff() {
+        assert_str_eq!(truncate_diff("-あ\n+い".to_string(), 10), "-あ\n+い");
+        assert_str_eq!(
+            truncate_diff("-ああ\n+いい\n+うう".to_string(), 8),
+            "-ああ\n+いい\n(差分が長いため、以降を省略しました)"
+        );
+    }
+
+    #[test]
+    fn test_line_diff_no_change() {
+        assert_str_eq!(line_diff("本文\n", "本文\n"), "");
+    }
 }